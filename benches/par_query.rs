@@ -0,0 +1,35 @@
+// Sequential vs parallel queries over a 1M-point cloud.
+//
+// cargo bench --features rayon
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use quaddy::{Point, QTree, Rect};
+
+fn cloud(n: usize) -> QTree<usize> {
+   let mut seed = 0x2545_f491_4f6c_dd1du64;
+   let mut next = move || {
+      seed ^= seed << 13;
+      seed ^= seed >> 7;
+      seed ^= seed << 17;
+      (seed >> 40) as f32 / (1u64 << 24) as f32
+   };
+   let boundary = Rect::new(0., 0., 1000., 1000.);
+   QTree::from_points(boundary, 8, (0..n).map(|i| (Point::new(next() * 1000., next() * 1000.), i)))
+}
+
+fn bench_queries(c: &mut Criterion) {
+   let qt = cloud(1_000_000);
+   let wide = Rect::new(100., 100., 800., 800.);
+   let mut found = Vec::new();
+   c.bench_function("query 1M wide", |b| {
+      b.iter(|| {
+         found.clear();
+         qt.query(&wide, &mut found);
+         found.len()
+      })
+   });
+   c.bench_function("par_query 1M wide", |b| b.iter(|| qt.par_query(&wide).len()));
+}
+
+criterion_group!(benches, bench_queries);
+criterion_main!(benches);
@@ -0,0 +1,2907 @@
+// QuadTree
+//
+// Point-region quadtree with no rendering dependencies. Drawing lives in the
+// examples, which walk the tree through the accessors below.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+use std::ops::ControlFlow;
+
+use num_traits::{Float, NumCast, ToPrimitive};
+
+/// A point in the plane.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Point<F = f32> {
+   pub x: F,
+   pub y: F,
+}
+
+impl<F: Float> Point<F> {
+   pub fn new(x: F, y: F) -> Self {
+      Self { x, y }
+   }
+
+   pub fn distance(&self, other: &Point<F>) -> F {
+      self.distance_squared(other).sqrt()
+   }
+
+   pub fn distance_squared(&self, other: &Point<F>) -> F {
+      let dx = self.x - other.x;
+      let dy = self.y - other.y;
+      dx * dx + dy * dy
+   }
+}
+
+/// Axis-aligned rectangle given by its top-left corner and its size.
+///
+/// Containment is half-open: the left and top edges belong to the rectangle,
+/// the right and bottom edges do not.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect<F = f32> {
+   pub x: F,
+   pub y: F,
+   pub w: F,
+   pub h: F,
+}
+
+impl<F: Float> Rect<F> {
+   pub fn new(x: F, y: F, w: F, h: F) -> Self {
+      Self { x, y, w, h }
+   }
+
+   pub fn contains(&self, p: &Point<F>) -> bool {
+      p.x >= self.x && p.x < self.x + self.w && p.y >= self.y && p.y < self.y + self.h
+   }
+
+   pub fn intersects(&self, other: &Rect<F>) -> bool {
+      self.x < other.x + other.w
+         && other.x < self.x + self.w
+         && self.y < other.y + other.h
+         && other.y < self.y + self.h
+   }
+
+   // Whether `other` lies entirely within this rectangle.
+   fn encloses(&self, other: &Rect<F>) -> bool {
+      other.x >= self.x
+         && other.x + other.w <= self.x + self.w
+         && other.y >= self.y
+         && other.y + other.h <= self.y + self.h
+   }
+
+   /// Squared distance from `p` to the closest point of the rectangle, zero
+   /// if `p` is inside.
+   pub fn distance_squared(&self, p: &Point<F>) -> F {
+      // not `clamp`, which panics on a negative size or NaN
+      let closest =
+         Point::new(p.x.max(self.x).min(self.x + self.w), p.y.max(self.y).min(self.y + self.h));
+      p.distance_squared(&closest)
+   }
+}
+
+/// Circle given by its center and radius. The circle includes its rim.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Circle<F = f32> {
+   pub x: F,
+   pub y: F,
+   pub r: F,
+}
+
+impl<F: Float> Circle<F> {
+   pub fn new(x: F, y: F, r: F) -> Self {
+      Self { x, y, r }
+   }
+
+   pub fn center(&self) -> Point<F> {
+      Point::new(self.x, self.y)
+   }
+
+   pub fn contains(&self, p: &Point<F>) -> bool {
+      p.distance_squared(&self.center()) <= self.r * self.r
+   }
+
+   /// Whether the circle overlaps `rect`, measured from the point of `rect`
+   /// closest to the center.
+   pub fn intersects(&self, rect: &Rect<F>) -> bool {
+      rect.distance_squared(&self.center()) <= self.r * self.r
+   }
+}
+
+/// A shape the tree can be queried with. `intersects_rect` may report
+/// overlaps that aren't there, which only costs extra node visits, but must
+/// never miss one.
+pub trait Region<F = f32> {
+   fn contains_point(&self, p: &Point<F>) -> bool;
+   fn intersects_rect(&self, r: &Rect<F>) -> bool;
+}
+
+impl<F: Float> Region<F> for Rect<F> {
+   fn contains_point(&self, p: &Point<F>) -> bool {
+      self.contains(p)
+   }
+
+   fn intersects_rect(&self, r: &Rect<F>) -> bool {
+      self.intersects(r)
+   }
+}
+
+impl<F: Float> Region<F> for Circle<F> {
+   fn contains_point(&self, p: &Point<F>) -> bool {
+      self.contains(p)
+   }
+
+   fn intersects_rect(&self, r: &Rect<F>) -> bool {
+      self.intersects(r)
+   }
+}
+
+// The union of several regions, for `QTree::query_shapes`.
+struct AnyOf<'a, F>(&'a [&'a dyn Region<F>]);
+
+impl<F> Region<F> for AnyOf<'_, F> {
+   fn contains_point(&self, p: &Point<F>) -> bool {
+      self.0.iter().any(|s| s.contains_point(p))
+   }
+
+   fn intersects_rect(&self, r: &Rect<F>) -> bool {
+      self.0.iter().any(|s| s.intersects_rect(r))
+   }
+}
+
+// Distances on a `w` by `h` world whose opposite edges meet, for trees built
+// with `QTree::with_wrap`.
+#[derive(Clone, Copy)]
+struct Torus<F> {
+   w: F,
+   h: F,
+}
+
+impl<F: Float> Torus<F> {
+   fn distance_squared(&self, a: &Point<F>, b: &Point<F>) -> F {
+      let dx = wrapped_gap(a.x - b.x, F::zero(), self.w);
+      let dy = wrapped_gap(a.y - b.y, F::zero(), self.h);
+      dx * dx + dy * dy
+   }
+
+   // Like `Rect::distance_squared`, going around an edge when that is shorter.
+   fn rect_distance_squared(&self, r: &Rect<F>, p: &Point<F>) -> F {
+      let dx = wrapped_gap(p.x - r.x, r.w, self.w);
+      let dy = wrapped_gap(p.y - r.y, r.h, self.h);
+      dx * dx + dy * dy
+   }
+}
+
+// A circle whose distances are measured on a torus.
+struct WrappedCircle<F> {
+   c: Circle<F>,
+   torus: Torus<F>,
+}
+
+impl<F: Float> Region<F> for WrappedCircle<F> {
+   fn contains_point(&self, p: &Point<F>) -> bool {
+      self.torus.distance_squared(&self.c.center(), p) <= self.c.r * self.c.r
+   }
+
+   fn intersects_rect(&self, r: &Rect<F>) -> bool {
+      self.torus.rect_distance_squared(r, &self.c.center()) <= self.c.r * self.c.r
+   }
+}
+
+/// Depth below which nodes stop splitting unless set with
+/// [`QTree::with_max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 16;
+
+/// Quadtree node. The root covers `boundary`; each node holds up to `cap`
+/// points, each with an attached value of type `T`, before it splits into
+/// four children. Nodes at `max_depth` never split and keep any overflow.
+///
+/// Coordinates are of type `F`, `f32` unless given. The payload comes first,
+/// so `QTree<f64>` is an `f32` tree holding `f64` values; use [`QTree64`]
+/// for `f64` coordinates.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(from = "QTreeData<T, F>"))]
+pub struct QTree<T = (), F = f32> {
+   boundary: Rect<F>,
+   cap: usize,
+   // capacity of nodes that no override covers
+   base_cap: usize,
+   max_depth: usize,
+   // whether distance queries go around the edges; only ever set on a root
+   wrap: bool,
+   points: Vec<(Point<F>, T)>,
+   // points in this node and all of its descendants
+   #[cfg_attr(feature = "serde", serde(skip_serializing))]
+   count: usize,
+   children: Option<Box<[QTree<T, F>; 4]>>,
+   // Capacity overrides reaching into this node, oldest first. They are
+   // resolved for each child when the node splits. At the root they are
+   // also kept when they cover it whole, for nodes added by growing.
+   overrides: Vec<(Rect<F>, usize)>,
+}
+
+/// A [`QTree`] with `f64` coordinates.
+pub type QTree64<T = ()> = QTree<T, f64>;
+
+impl<T, F: Float> QTree<T, F> {
+   pub fn new(boundary: Rect<F>, cap: usize) -> Self {
+      Self {
+         boundary,
+         cap,
+         base_cap: cap,
+         max_depth: DEFAULT_MAX_DEPTH,
+         wrap: false,
+         points: Vec::new(),
+         count: 0,
+         children: None,
+         overrides: Vec::new(),
+      }
+   }
+
+   /// Builds a tree from `points` and their values. Points outside `boundary`
+   /// are skipped, as with `insert`.
+   pub fn from_points<I>(boundary: Rect<F>, cap: usize, points: I) -> Self
+   where
+      I: IntoIterator<Item = (Point<F>, T)>,
+   {
+      let mut qt = Self::new(boundary, cap);
+      qt.extend(points);
+      qt
+   }
+
+   /// Sets how deep the tree may subdivide. Points that would go deeper stay
+   /// in the node at `max_depth`, ignoring `cap`, so many points with the same
+   /// coordinates cannot split the tree forever.
+   pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+      self.max_depth = max_depth;
+      self
+   }
+
+   /// Makes `nearest`, `k_nearest`, `query_circle` and `pairs_within` treat
+   /// the boundary as a torus, where leaving through one edge comes back in
+   /// through the opposite one. Along each axis they use the shorter of the
+   /// direct and the wrapped distance. Other queries, and the nodes returned
+   /// by `children`, don't wrap. A boundary of zero or negative size never
+   /// wraps.
+   pub fn with_wrap(mut self, wrap: bool) -> Self {
+      self.wrap = wrap;
+      self
+   }
+
+   pub fn wraps(&self) -> bool {
+      self.wrap
+   }
+
+   fn torus(&self) -> Option<Torus<F>> {
+      let (w, h) = (self.boundary.w, self.boundary.h);
+      (self.wrap && w > F::zero() && h > F::zero()).then_some(Torus { w, h })
+   }
+
+   pub fn boundary(&self) -> &Rect<F> {
+      &self.boundary
+   }
+
+   pub fn cap(&self) -> usize {
+      self.cap
+   }
+
+   pub fn max_depth(&self) -> usize {
+      self.max_depth
+   }
+
+   /// Number of points stored in this node and all of its descendants.
+   pub fn len(&self) -> usize {
+      self.count
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.count == 0
+   }
+
+   /// Points stored directly in this node, not including its children.
+   pub fn points(&self) -> &[(Point<F>, T)] {
+      &self.points
+   }
+
+   fn divided(&self) -> bool {
+      self.children.is_some()
+   }
+
+   /// The four children in NW, NE, SW, SE order, if this node is divided.
+   pub fn children(&self) -> Option<&[QTree<T, F>; 4]> {
+      self.children.as_deref()
+   }
+
+   /// Depth of the deepest node, 0 for an undivided tree.
+   pub fn depth(&self) -> usize {
+      self
+         .children()
+         .map_or(0, |c| 1 + c.iter().map(QTree::depth).max().unwrap())
+   }
+
+   /// Number of nodes, including this one.
+   pub fn node_count(&self) -> usize {
+      1 + self
+         .children()
+         .map_or(0, |c| c.iter().map(QTree::node_count).sum())
+   }
+
+   /// Number of undivided nodes.
+   pub fn leaf_count(&self) -> usize {
+      self
+         .children()
+         .map_or(1, |c| c.iter().map(QTree::leaf_count).sum())
+   }
+
+   /// Calls `f` with the boundary and stored points of every node,
+   /// depth-first: a node, then each child in NW, NE, SW, SE order.
+   pub fn visit_nodes<V>(&self, f: &mut V)
+   where
+      V: FnMut(&Rect<F>, &[(Point<F>, T)]),
+   {
+      f(&self.boundary, &self.points);
+      if let Some(children) = self.children() {
+         for c in children.iter() {
+            c.visit_nodes(f);
+         }
+      }
+   }
+
+   /// Splits this node into four quadrants. Does nothing if it is already
+   /// divided.
+   ///
+   /// Neighbouring quadrants share the same split line, so they tile the
+   /// parent without gaps or overlap.
+   pub fn subdivide(&mut self) {
+      if self.divided() {
+         return;
+      }
+      let (x0, y0) = (self.boundary.x, self.boundary.y);
+      let (x2, y2) = (x0 + self.boundary.w, y0 + self.boundary.h);
+      let (x1, y1) = (x0 + self.boundary.w / two(), y0 + self.boundary.h / two());
+      let child = |r| self.new_child(r);
+      self.children = Some(Box::new([
+         child(Rect::new(x0, y0, x1 - x0, y1 - y0)),
+         child(Rect::new(x1, y0, x2 - x1, y1 - y0)),
+         child(Rect::new(x0, y1, x1 - x0, y2 - y1)),
+         child(Rect::new(x1, y1, x2 - x1, y2 - y1)),
+      ]));
+   }
+
+   // An empty node covering `r`, which lies inside this node, with the
+   // capacity and pending overrides it inherits from this node.
+   fn new_child(&self, r: Rect<F>) -> QTree<T, F> {
+      // the newest override covering the whole child sets its cap; newer ones
+      // covering only part of it stay pending
+      let last = self.overrides.iter().rposition(|(o, _)| o.encloses(&r));
+      let cap = last.map_or(self.cap, |i| self.overrides[i].1);
+      let pending = &self.overrides[last.map_or(0, |i| i + 1)..];
+      let mut c = QTree::new(r, cap).with_max_depth(self.max_depth);
+      c.base_cap = self.base_cap;
+      c.overrides = pending
+         .iter()
+         .filter(|(o, _)| o.intersects(&r))
+         .copied()
+         .collect();
+      c
+   }
+
+   /// Uses `cap` instead of the tree's capacity for every node lying entirely
+   /// inside `r`, including nodes created there later. Where overrides
+   /// overlap, the most recent call wins.
+   pub fn set_capacity_in(&mut self, r: &Rect<F>, cap: usize) {
+      if !self.boundary.intersects(r) {
+         return;
+      }
+
+      if r.encloses(&self.boundary) {
+         self.set_capacity(r, cap);
+         return;
+      }
+
+      self.overrides.push((*r, cap));
+      if self.divided() {
+         for c in self.children.as_mut().unwrap().iter_mut() {
+            c.set_capacity_in(r, cap);
+         }
+      }
+   }
+
+   // `r` covers this whole node, so older overrides no longer matter here.
+   // `r` itself is kept for nodes created outside this one later.
+   fn set_capacity(&mut self, r: &Rect<F>, cap: usize) {
+      self.cap = cap;
+      self.overrides.clear();
+      self.overrides.push((*r, cap));
+      if self.divided() {
+         for c in self.children.as_mut().unwrap().iter_mut() {
+            c.set_capacity(r, cap);
+         }
+      }
+   }
+
+   // The cap of the newest override covering all of `r`, else `base_cap`.
+   fn cap_for(&self, r: &Rect<F>, base_cap: usize) -> usize {
+      let covering = self.overrides.iter().rev().find(|(o, _)| o.encloses(r));
+      covering.map_or(base_cap, |&(_, cap)| cap)
+   }
+
+   // An empty root covering `boundary` with the given base capacity and depth
+   // limit, and this node's overrides and wrap flag.
+   fn empty_root(&self, boundary: Rect<F>, base_cap: usize, max_depth: usize) -> QTree<T, F> {
+      let cap = self.cap_for(&boundary, base_cap);
+      let mut qt = QTree::new(boundary, cap)
+         .with_max_depth(max_depth)
+         .with_wrap(self.wrap);
+      qt.base_cap = base_cap;
+      qt.overrides = self.overrides.clone();
+      qt
+   }
+
+   /// Removes every point and all children, keeping the boundary, capacity
+   /// and capacity overrides so the tree can be refilled.
+   pub fn clear(&mut self) {
+      self.points.clear();
+      self.count = 0;
+      self.children = None;
+   }
+
+   /// Inserts `p` with its `value`, returning `false` (and dropping `value`)
+   /// if `p` lies outside the boundary.
+   pub fn insert(&mut self, p: Point<F>, value: T) -> bool {
+      if !self.boundary.contains(&p) {
+         return false;
+      }
+      self.insert_inside(p, value, 0);
+      true
+   }
+
+   // `p` is known to be inside this node. Children are picked by comparing
+   // against the split lines rather than each child's `contains`, which could
+   // reject a point at the far edge once `x + w` is rounded.
+   fn insert_inside(&mut self, p: Point<F>, value: T, depth: usize) {
+      self.count += 1;
+      if self.points.len() < self.cap || (depth >= self.max_depth && !self.divided()) {
+         self.points.push((p, value));
+         return;
+      }
+
+      if !self.divided() {
+         self.subdivide();
+      }
+
+      let i = self.child_index(&p);
+      self.children.as_mut().unwrap()[i].insert_inside(p, value, depth + 1);
+   }
+
+   /// Index into `children()` of the child that `p` routes to, or `None` if
+   /// this node is not divided. `p` is taken to lie inside the node.
+   ///
+   /// The split lines are read off the children, so this also matches roots
+   /// built by `insert_growing`, which don't split at their midpoint.
+   pub fn child_index_of(&self, p: &Point<F>) -> Option<usize> {
+      self.divided().then(|| self.child_index(p))
+   }
+
+   // Index of the child whose quadrant holds `p`, reading the split lines off
+   // the SE child. Only valid on a divided node.
+   fn child_index(&self, p: &Point<F>) -> usize {
+      let split = &self.children.as_ref().unwrap()[3].boundary;
+      (p.x >= split.x) as usize + 2 * (p.y >= split.y) as usize
+   }
+
+   /// Inserts `p` like `insert`, but first grows the tree until `p` fits.
+   ///
+   /// Each step wraps the current root as one quadrant of a new root twice
+   /// its size, extended towards `p`, so the existing structure is kept.
+   /// Returns `false`, leaving the tree alone, if `p` is not finite, the
+   /// boundary is empty, or reaching `p` would grow the boundary past the
+   /// largest finite coordinate.
+   pub fn insert_growing(&mut self, p: Point<F>, value: T) -> bool {
+      if !p.x.is_finite()
+         || !p.y.is_finite()
+         || self.boundary.w <= F::zero()
+         || self.boundary.h <= F::zero()
+      {
+         return false;
+      }
+      let mut b = self.boundary;
+      while !b.contains(&p) {
+         match grown_quadrants(&b, &p) {
+            Some((outer, _)) => b = outer,
+            None => return false,
+         }
+      }
+      while !self.boundary.contains(&p) {
+         self.grow_towards(&p);
+      }
+      self.insert(p, value)
+   }
+
+   // One step of `insert_growing`; the caller has checked that it stays
+   // finite.
+   fn grow_towards(&mut self, p: &Point<F>) {
+      let (outer, quadrants) = grown_quadrants(&self.boundary, p).unwrap();
+      let (left, up) = (p.x < self.boundary.x, p.y < self.boundary.y);
+      // overrides reaching past the old root also apply to the new nodes
+      let root = self.empty_root(outer, self.base_cap, self.max_depth);
+      let mut children = quadrants.map(|r| root.new_child(r));
+      let i = left as usize + 2 * up as usize;
+      children[i] = std::mem::replace(self, root);
+      children[i].wrap = false;
+      self.count = children[i].count;
+      self.children = Some(Box::new(children));
+   }
+
+   /// Enlarges the boundary just enough to contain `p`, so it can then be
+   /// inserted. Returns `false` if `p` is not finite.
+   ///
+   /// An undivided tree only has its boundary moved. Once the root is
+   /// divided the old quadrants no longer tile the new boundary, so every
+   /// point is taken out and reinserted into a fresh tree with the same
+   /// capacity, depth limit and capacity overrides.
+   pub fn extend_bounds_to_include(&mut self, p: &Point<F>) -> bool {
+      if !p.x.is_finite() || !p.y.is_finite() {
+         return false;
+      }
+      if self.boundary.contains(p) {
+         return true;
+      }
+
+      let b = self.boundary;
+      let (x0, y0) = (b.x.min(p.x), b.y.min(p.y));
+      // the right and bottom edges are exclusive, so push them past `p`
+      let w = reach_past(x0, (b.x + b.w).max(p.x) - x0, p.x);
+      let h = reach_past(y0, (b.y + b.h).max(p.y) - y0, p.y);
+      let grown = Rect::new(x0, y0, w, h);
+
+      if !self.divided() {
+         self.cap = self.cap_for(&grown, self.base_cap);
+         self.boundary = grown;
+         return true;
+      }
+
+      let mut points = Vec::with_capacity(self.count);
+      self.drain_into(&mut points);
+      let mut qt = self.empty_root(grown, self.base_cap, self.max_depth);
+      qt.extend(points);
+      *self = qt;
+      true
+   }
+
+   /// Rebuilds the tree with `max_depth` set to `target_depth` and a capacity
+   /// of `ceil(len / 4^target_depth)`, at least 1, so evenly spread points
+   /// fill the leaves at that depth. Capacity overrides are kept.
+   pub fn rebuild_to_max_depth(&mut self, target_depth: usize) {
+      let cap = u32::try_from(target_depth)
+         .ok()
+         .and_then(|d| 4usize.checked_pow(d))
+         .map_or(1, |leaves| self.count.div_ceil(leaves))
+         .max(1);
+      let mut points = Vec::with_capacity(self.count);
+      self.drain_into(&mut points);
+      let mut qt = self.empty_root(self.boundary, cap, target_depth);
+      qt.extend(points);
+      *self = qt;
+   }
+
+   // Moves every point out of this node and its descendants into `out`.
+   fn drain_into(&mut self, out: &mut Vec<(Point<F>, T)>) {
+      out.append(&mut self.points);
+      if let Some(children) = self.children.as_mut() {
+         for c in children.iter_mut() {
+            c.drain_into(out);
+         }
+      }
+   }
+
+   /// Removes one stored point equal to `p` and returns its value, or `None`
+   /// if there is no such point.
+   ///
+   /// A divided node whose four children end up as empty leaves collapses
+   /// back into a leaf.
+   pub fn remove(&mut self, p: &Point<F>) -> Option<T> {
+      if !self.boundary.contains(p) {
+         return None;
+      }
+      self.remove_inside(p)
+   }
+
+   fn remove_inside(&mut self, p: &Point<F>) -> Option<T> {
+      if let Some(i) = self.points.iter().position(|(q, _)| q == p) {
+         self.count -= 1;
+         return Some(self.points.swap_remove(i).1);
+      }
+
+      if !self.divided() {
+         return None;
+      }
+
+      let i = self.child_index(p);
+      let children = self.children.as_mut().unwrap();
+      let removed = children[i].remove_inside(p)?;
+      self.count -= 1;
+      if children.iter().all(|c| !c.divided() && c.points.is_empty()) {
+         self.children = None;
+      }
+      Some(removed)
+   }
+
+   /// Moves one stored point equal to `old` to `new`, keeping its value.
+   /// Returns `false`, leaving the tree unchanged, if there is no such point
+   /// or `new` lies outside the boundary.
+   ///
+   /// If `new` still falls in the node holding `old`, only the coordinate is
+   /// changed; otherwise the point is removed and inserted again.
+   pub fn update(&mut self, old: &Point<F>, new: Point<F>) -> bool {
+      if !self.boundary.contains(old) || !self.boundary.contains(&new) {
+         return false;
+      }
+      match self.move_inside(old, &new, true) {
+         None => false,
+         Some(true) => true,
+         Some(false) => {
+            let value = self.remove_inside(old).unwrap();
+            self.insert(new, value);
+            true
+         }
+      }
+   }
+
+   // Follows `old` down the tree like `remove_inside`. `fits` says whether
+   // `new` takes the same path so far; if it still does at the node holding
+   // `old`, the point is moved there. Returns `None` if `old` isn't stored
+   // and `Some(false)` if it was found but not moved.
+   fn move_inside(&mut self, old: &Point<F>, new: &Point<F>, fits: bool) -> Option<bool> {
+      if let Some(i) = self.points.iter().position(|(q, _)| q == old) {
+         if fits {
+            self.points[i].0 = *new;
+         }
+         return Some(fits);
+      }
+
+      if !self.divided() {
+         return None;
+      }
+
+      let i = self.child_index(old);
+      let fits = fits && self.child_index(new) == i;
+      self.children.as_mut().unwrap()[i].move_inside(old, new, fits)
+   }
+
+   /// Collapses every divided node whose children are all empty leaves,
+   /// bottom up, and returns the number of nodes freed.
+   ///
+   /// `remove` already does this along the path it takes; this catches empty
+   /// quadrants left by `subdivide` or `insert_growing`.
+   pub fn prune(&mut self) -> usize {
+      if !self.divided() {
+         return 0;
+      }
+      let children = self.children.as_mut().unwrap();
+      let freed = children.iter_mut().map(QTree::prune).sum::<usize>();
+      if children.iter().all(|c| !c.divided() && c.points.is_empty()) {
+         self.children = None;
+         return freed + 4;
+      }
+      freed
+   }
+
+   /// Appends every stored point inside `outer` but within `thickness` of its
+   /// edges to `found`, i.e. `outer` minus `outer` shrunk by `thickness` on
+   /// every side. A `thickness` of half the rect or more selects all of it.
+   pub fn query_border<'a>(
+      &'a self,
+      outer: &Rect<F>,
+      thickness: F,
+      found: &mut Vec<(&'a Point<F>, &'a T)>,
+   ) {
+      let inner = Rect::new(
+         outer.x + thickness,
+         outer.y + thickness,
+         outer.w - two::<F>() * thickness,
+         outer.h - two::<F>() * thickness,
+      );
+      self.border_into(outer, &inner, found);
+   }
+
+   fn border_into<'a>(
+      &'a self,
+      outer: &Rect<F>,
+      inner: &Rect<F>,
+      found: &mut Vec<(&'a Point<F>, &'a T)>,
+   ) {
+      if !self.boundary.intersects(outer) || inner.encloses(&self.boundary) {
+         return;
+      }
+
+      for (p, v) in &self.points {
+         if outer.contains(p) && !inner.contains(p) {
+            found.push((p, v))
+         }
+      }
+
+      if self.divided() {
+         for c in self.children.as_ref().unwrap().iter() {
+            c.border_into(outer, inner, found);
+         }
+      }
+   }
+
+   /// Compacts the tree after heavy churn. Divided nodes whose children are
+   /// all leaves are merged back into a leaf when their points fit in `cap`,
+   /// working bottom-up, and every remaining point vec is shrunk to fit.
+   pub fn defragment(&mut self) {
+      if self.divided() {
+         let children = self.children.as_mut().unwrap();
+         for c in children.iter_mut() {
+            c.defragment();
+         }
+
+         let total = self.points.len() + children.iter().map(|c| c.points.len()).sum::<usize>();
+         if children.iter().all(|c| !c.divided()) && total <= self.cap {
+            for c in children.iter_mut() {
+               self.points.append(&mut c.points);
+            }
+            self.children = None;
+         }
+      }
+      self.points.shrink_to_fit();
+   }
+
+   /// Iterates over every stored point and its value, depth-first: a node's
+   /// own points, then each child in NW, NE, SW, SE order.
+   pub fn iter(&self) -> Iter<'_, T, F> {
+      Iter {
+         stack: vec![self],
+         points: [].iter(),
+      }
+   }
+
+   /// Appends every stored point inside `r`, with its value, to `found`.
+   pub fn query<'a, R>(&'a self, r: &R, found: &mut Vec<(&'a Point<F>, &'a T)>)
+   where
+      R: Region<F> + ?Sized,
+   {
+      self.query_visit(r, &mut |p, v| found.push((p, v)));
+   }
+
+   /// Calls `f` with every stored point inside `r` and its value, without
+   /// collecting them.
+   pub fn query_visit<'a, R, V>(&'a self, r: &R, f: &mut V)
+   where
+      R: Region<F> + ?Sized,
+      V: FnMut(&'a Point<F>, &'a T),
+   {
+      let _ = self.query_visit_controlled(r, &mut |p, v| {
+         f(p, v);
+         ControlFlow::Continue(())
+      });
+   }
+
+   /// Calls `f` with every stored point inside `r` and its value until it
+   /// returns `Break`, which stops the whole traversal. Returns `Break` if
+   /// that happened.
+   pub fn query_visit_controlled<'a, R, V>(&'a self, r: &R, f: &mut V) -> ControlFlow<()>
+   where
+      R: Region<F> + ?Sized,
+      V: FnMut(&'a Point<F>, &'a T) -> ControlFlow<()>,
+   {
+      if !r.intersects_rect(&self.boundary) {
+         return ControlFlow::Continue(());
+      }
+
+      for (p, v) in &self.points {
+         if r.contains_point(p) {
+            f(p, v)?;
+         }
+      }
+
+      if self.divided() {
+         for c in self.children.as_ref().unwrap().iter() {
+            c.query_visit_controlled(r, f)?;
+         }
+      }
+      ControlFlow::Continue(())
+   }
+
+   /// Number of stored points inside `r`. Nodes lying entirely inside `r`
+   /// contribute their subtree count without looking at their points.
+   pub fn count_in(&self, r: &Rect<F>) -> usize {
+      if !self.boundary.intersects(r) {
+         return 0;
+      }
+      if r.encloses(&self.boundary) {
+         return self.count;
+      }
+
+      let mut n = self.points.iter().filter(|(p, _)| r.contains(p)).count();
+      if self.divided() {
+         for c in self.children.as_ref().unwrap().iter() {
+            n += c.count_in(r);
+         }
+      }
+      n
+   }
+
+   /// Appends every stored point inside `r` for which `pred` holds, with its
+   /// value, to `found`.
+   pub fn query_filter<'a, R, P>(
+      &'a self,
+      r: &R,
+      mut pred: P,
+      found: &mut Vec<(&'a Point<F>, &'a T)>,
+   ) where
+      R: Region<F> + ?Sized,
+      P: FnMut(&Point<F>, &T) -> bool,
+   {
+      self.query_visit(r, &mut |p, v| {
+         if pred(p, v) {
+            found.push((p, v))
+         }
+      });
+   }
+
+   /// Calls `mark` with the value of every stored point inside `r`, allowing
+   /// it to be changed, and returns the points for which `mark` returned
+   /// `true`. Points themselves can't be moved this way.
+   pub fn query_and_mark<R, M>(&mut self, r: &R, mut mark: M) -> Vec<Point<F>>
+   where
+      R: Region<F> + ?Sized,
+      M: FnMut(&mut T) -> bool,
+   {
+      let mut found = vec![];
+      self.mark_into(r, &mut mark, &mut found);
+      found
+   }
+
+   fn mark_into<R, M>(&mut self, r: &R, mark: &mut M, found: &mut Vec<Point<F>>)
+   where
+      R: Region<F> + ?Sized,
+      M: FnMut(&mut T) -> bool,
+   {
+      if !r.intersects_rect(&self.boundary) {
+         return;
+      }
+
+      for (p, v) in self.points.iter_mut() {
+         if r.contains_point(p) && mark(v) {
+            found.push(*p);
+         }
+      }
+
+      if self.divided() {
+         for c in self.children.as_mut().unwrap().iter_mut() {
+            c.mark_into(r, mark, found);
+         }
+      }
+   }
+
+   /// Appends every stored point inside `c`, with its value, to `found`.
+   /// On a wrapping tree the circle reaches around the edges.
+   pub fn query_circle<'a>(&'a self, c: &Circle<F>, found: &mut Vec<(&'a Point<F>, &'a T)>) {
+      match self.torus() {
+         Some(torus) => self.query(&WrappedCircle { c: *c, torus }, found),
+         None => self.query(c, found),
+      }
+   }
+
+   /// Like `query_circle`, but also reports how the query went through the
+   /// tree.
+   pub fn query_circle_with_stats(
+      &self,
+      c: &Circle<F>,
+   ) -> (Vec<(&Point<F>, &T)>, CircleQueryStats) {
+      let mut found = vec![];
+      let mut stats = CircleQueryStats::default();
+      self.circle_stats_into(c, &mut found, &mut stats);
+      (found, stats)
+   }
+
+   fn circle_stats_into<'a>(
+      &'a self,
+      c: &Circle<F>,
+      found: &mut Vec<(&'a Point<F>, &'a T)>,
+      stats: &mut CircleQueryStats,
+   ) {
+      if !c.intersects(&self.boundary) {
+         stats.culled += 1;
+         return;
+      }
+
+      stats.visited += 1;
+      stats.points_tested += self.points.len();
+      for (p, v) in &self.points {
+         if c.contains(p) {
+            found.push((p, v))
+         }
+      }
+
+      if self.divided() {
+         for child in self.children.as_ref().unwrap().iter() {
+            child.circle_stats_into(c, found, stats);
+         }
+      }
+   }
+
+   /// Every stored point inside at least one of `shapes`, with its value.
+   ///
+   /// The tree is walked once for all shapes, so a stored point inside
+   /// several of them is still returned once.
+   pub fn query_shapes(&self, shapes: &[&dyn Region<F>]) -> Vec<(&Point<F>, &T)> {
+      let mut found = vec![];
+      self.query(&AnyOf(shapes), &mut found);
+      found
+   }
+
+   /// Every unordered pair of stored points at most `d` apart, each reported
+   /// once, found with a circle query around every point. The queries span
+   /// the whole tree, so pairs split across nodes are found too. A negative
+   /// or NaN `d` finds nothing. On a wrapping tree pairs may be close across
+   /// an edge.
+   pub fn pairs_within<'a>(&'a self, d: F) -> Vec<(&'a Point<F>, &'a Point<F>)> {
+      let mut pairs = vec![];
+      if d < F::zero() || d.is_nan() {
+         return pairs;
+      }
+      let torus = self.torus();
+      for (p, _) in self.iter() {
+         let c = Circle::new(p.x, p.y, d);
+         // every point is stored once, so its address tells the two
+         // directions of a pair apart
+         let mut keep = |q: &'a Point<F>, _: &'a T| {
+            if std::ptr::from_ref(q) > std::ptr::from_ref(p) {
+               pairs.push((p, q));
+            }
+         };
+         match torus {
+            Some(torus) => self.query_visit(&WrappedCircle { c, torus }, &mut keep),
+            None => self.query_visit(&c, &mut keep),
+         }
+      }
+      pairs
+   }
+
+   /// Returns the stored point closest to `p`, with its value.
+   ///
+   /// Nodes are visited closest first, and the search stops once the next
+   /// node is farther away than the best point found so far. On a wrapping
+   /// tree distances are measured around the edges.
+   pub fn nearest(&self, p: &Point<F>) -> Option<(&Point<F>, &T)> {
+      let (to_point, to_rect) = self.metric(p);
+      let mut best: Option<(F, (&Point<F>, &T))> = None;
+      let mut queue = BinaryHeap::new();
+      queue.push(Reverse(Candidate {
+         key: to_rect(&self.boundary),
+         seq: 0,
+         item: self,
+      }));
+
+      while let Some(Reverse(Candidate {
+         key, item: node, ..
+      })) = queue.pop()
+      {
+         if best.is_some_and(|(d, _)| key > d) {
+            break;
+         }
+
+         for (q, v) in &node.points {
+            let d = to_point(q);
+            if best.is_none_or(|(b, _)| d < b) {
+               best = Some((d, (q, v)));
+            }
+         }
+
+         if node.divided() {
+            for c in node.children.as_ref().unwrap().iter() {
+               let d = to_rect(&c.boundary);
+               if best.is_none_or(|(b, _)| d <= b) {
+                  queue.push(Reverse(Candidate {
+                     key: d,
+                     seq: 0,
+                     item: c,
+                  }));
+               }
+            }
+         }
+      }
+      best.map(|(_, item)| item)
+   }
+
+   /// Returns the `k` stored points closest to `p`, nearest first. Points at
+   /// equal distance come back in no particular order. On a wrapping tree
+   /// distances are measured around the edges.
+   pub fn k_nearest(&self, p: &Point<F>, k: usize) -> Vec<(&Point<F>, &T)> {
+      let (to_point, to_rect) = self.metric(p);
+      let mut best = Best::new(k);
+      let mut queue = BinaryHeap::new();
+      queue.push(Reverse(Candidate {
+         key: to_rect(&self.boundary),
+         seq: 0,
+         item: self,
+      }));
+
+      while let Some(Reverse(Candidate {
+         key, item: node, ..
+      })) = queue.pop()
+      {
+         if key > best.bound() {
+            break;
+         }
+
+         for (q, v) in &node.points {
+            best.push(to_point(q), (q, v));
+         }
+
+         if node.divided() {
+            for c in node.children.as_ref().unwrap().iter() {
+               let d = to_rect(&c.boundary);
+               if d <= best.bound() {
+                  queue.push(Reverse(Candidate {
+                     key: d,
+                     seq: 0,
+                     item: c,
+                  }));
+               }
+            }
+         }
+      }
+      best.into_sorted()
+   }
+
+   // Squared distances from `p` to a point and to a rect, as `nearest` and
+   // `k_nearest` measure them.
+   fn metric<'p>(
+      &self,
+      p: &'p Point<F>,
+   ) -> (impl Fn(&Point<F>) -> F + 'p, impl Fn(&Rect<F>) -> F + 'p) {
+      let torus = self.torus();
+      let to_point = move |q: &Point<F>| match torus {
+         Some(t) => t.distance_squared(q, p),
+         None => q.distance_squared(p),
+      };
+      let to_rect = move |r: &Rect<F>| match torus {
+         Some(t) => t.rect_distance_squared(r, p),
+         None => r.distance_squared(p),
+      };
+      (to_point, to_rect)
+   }
+
+   /// Returns the `n` points inside `r` with the highest `score`, best first.
+   /// `score` receives each point, its value and its distance to `center`.
+   ///
+   /// Ties are broken in favour of the point found first in depth-first
+   /// order. Points scored NaN are skipped.
+   pub fn query_top<S>(
+      &self,
+      r: &Rect<F>,
+      center: &Point<F>,
+      n: usize,
+      score: S,
+   ) -> Vec<(&Point<F>, &T)>
+   where
+      S: Fn(&Point<F>, &T, F) -> F,
+   {
+      let mut best = Best::new(n);
+      // smaller keys are better, so negate the score
+      self.query_visit(r, &mut |p, v| {
+         let s = score(p, v, p.distance(center));
+         if !s.is_nan() {
+            best.push(-s, (p, v));
+         }
+      });
+      best.into_sorted()
+   }
+
+   /// Draws up to `n` distinct points inside `r`, spread evenly over the
+   /// tree's cells instead of following the point density.
+   ///
+   /// Matches are grouped by the node storing them. Each round takes one
+   /// random point from every group, so every occupied cell contributes
+   /// before any cell contributes twice. Within a round, groups come in a
+   /// random order weighted by how much of `r` their cell covers, so when `n`
+   /// runs out mid-round, large sparse cells are not crowded out by the many
+   /// small cells of a dense area. `rng` must return uniform values in
+   /// `[0, 1)`.
+   pub fn query_spread_sample<R>(&self, r: &Rect<F>, n: usize, rng: &mut R) -> Vec<(&Point<F>, &T)>
+   where
+      R: FnMut() -> f32,
+   {
+      let mut strata = vec![];
+      self.strata_into(r, &mut strata);
+
+      let mut sample = vec![];
+      while sample.len() < n && !strata.is_empty() {
+         // weighted random order: larger keys first, keys ln(u) / area
+         let mut keyed: Vec<_> = strata
+            .iter_mut()
+            .map(|(area, s)| (cast::<F>(rng().max(f32::MIN_POSITIVE).ln()) / *area, s))
+            .collect();
+         keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+         for (_, s) in keyed.into_iter().take(n - sample.len()) {
+            let i = pick(rng, s.len());
+            sample.push(s.swap_remove(i));
+         }
+         strata.retain(|(_, s)| !s.is_empty());
+      }
+      sample
+   }
+
+   fn strata_into<'a>(&'a self, r: &Rect<F>, strata: &mut Vec<Stratum<'a, T, F>>) {
+      if !self.boundary.intersects(r) {
+         return;
+      }
+
+      let here: Vec<_> = self
+         .points
+         .iter()
+         .filter(|(p, _)| r.contains(p))
+         .map(|(p, v)| (p, v))
+         .collect();
+      if !here.is_empty() {
+         let b = &self.boundary;
+         let w = (b.x + b.w).min(r.x + r.w) - b.x.max(r.x);
+         let h = (b.y + b.h).min(r.y + r.h) - b.y.max(r.y);
+         strata.push((w * h, here));
+      }
+
+      if self.divided() {
+         for c in self.children.as_ref().unwrap().iter() {
+            c.strata_into(r, strata);
+         }
+      }
+   }
+
+   /// Average number of points a `query` over each of `rects` returns, or 0
+   /// for no rects. Useful with representative rects when tuning `cap`.
+   pub fn mean_points_per_query(&self, rects: &[Rect<F>]) -> f32 {
+      let mut total = 0;
+      for r in rects {
+         self.query_visit(r, &mut |_, _| total += 1);
+      }
+      mean(total, rects.len())
+   }
+
+   /// Average number of nodes a `query` over each of `rects` visits,
+   /// counting nodes that are pruned after the intersection test, or 0 for no
+   /// rects.
+   pub fn mean_nodes_per_query(&self, rects: &[Rect<F>]) -> f32 {
+      let total = rects.iter().map(|r| self.nodes_visited(r)).sum();
+      mean(total, rects.len())
+   }
+
+   /// Number of nodes a `query` over `r` visits, counting nodes that are
+   /// pruned after the intersection test.
+   pub fn nodes_visited(&self, r: &Rect<F>) -> usize {
+      let mut n = 1;
+      if self.divided() && self.boundary.intersects(r) {
+         for c in self.children.as_ref().unwrap().iter() {
+            n += c.nodes_visited(r);
+         }
+      }
+      n
+   }
+
+   /// Counts the points inside `r` on a `cols` x `rows` grid spanning `r`.
+   /// Cells are stored row by row; counts saturate at `u16::MAX`.
+   pub fn rasterize_query(&self, r: &Rect<F>, cols: usize, rows: usize) -> Vec<u16> {
+      let mut grid = vec![0u16; cols * rows];
+      if !grid.is_empty() {
+         self.query_visit(r, &mut |p, _| {
+            let col = ((p.x - r.x) / r.w * cast(cols))
+               .to_usize()
+               .unwrap_or(0)
+               .min(cols - 1);
+            let row = ((p.y - r.y) / r.h * cast(rows))
+               .to_usize()
+               .unwrap_or(0)
+               .min(rows - 1);
+            let cell = &mut grid[row * cols + col];
+            *cell = cell.saturating_add(1);
+         });
+      }
+      grid
+   }
+
+   /// Splits all stored points into separate x and y coordinate vecs.
+   ///
+   /// Points come in depth-first order: a node's own points, then its
+   /// children in NW, NE, SW, SE order. The order only depends on the tree's
+   /// contents, so a payload array built by the same walk lines up.
+   pub fn into_xy_arrays(self) -> (Vec<F>, Vec<F>) {
+      self.iter().map(|(p, _)| (p.x, p.y)).unzip()
+   }
+
+   /// Renders the leaves as a `width` x `height` RGBA image covering the root
+   /// boundary, returned as `(width, height, pixels)` with rows top to bottom.
+   ///
+   /// A leaf's density is the number of points inside its cell, including
+   /// those stored higher up in the tree. Empty cells are black; occupied
+   /// cells run from blue to red as their density approaches that of the most
+   /// populated leaf. A pixel takes the color of the cell containing its
+   /// center.
+   pub fn to_density_image(&self, width: u32, height: u32) -> (u32, u32, Vec<u8>) {
+      let mut leaves = vec![];
+      self.leaf_densities(&mut vec![], &mut leaves);
+      let max = leaves.iter().map(|&(_, n)| n).max().unwrap_or(0);
+
+      let b = self.boundary;
+      // first pixel whose center is at or past `v`
+      let first = |v: F, origin: F, extent: F, n: u32| {
+         let first = ((v - origin) / extent * cast(n) - cast(0.5))
+            .ceil()
+            .max(F::zero());
+         first.to_u32().unwrap_or(u32::MAX).min(n)
+      };
+      let mut pixels = vec![0; width as usize * height as usize * 4];
+      for (r, n) in leaves {
+         let color = if n == 0 {
+            [0, 0, 0, 255]
+         } else {
+            let red = (n as f32 / max as f32 * 255.).round() as u8;
+            [red, 0, 255 - red, 255]
+         };
+         for py in first(r.y, b.y, b.h, height)..first(r.y + r.h, b.y, b.h, height) {
+            for px in first(r.x, b.x, b.w, width)..first(r.x + r.w, b.x, b.w, width) {
+               let i = (py as usize * width as usize + px as usize) * 4;
+               pixels[i..i + 4].copy_from_slice(&color);
+            }
+         }
+      }
+      (width, height, pixels)
+   }
+
+   /// Whether every leaf cell overlapping `r` holds at least one point,
+   /// counted as for `to_density_image`. Occupancy is only known per cell, so
+   /// a cell with a single point in one corner counts as fully occupied. True
+   /// if `r` overlaps no leaf at all.
+   pub fn is_fully_occupied(&self, r: &Rect<F>) -> bool {
+      self.occupied_in(r, &mut vec![])
+   }
+
+   fn occupied_in(&self, r: &Rect<F>, ancestors: &mut Vec<Point<F>>) -> bool {
+      if !self.boundary.intersects(r) {
+         return true;
+      }
+      if !self.divided() {
+         return !self.points.is_empty() || ancestors.iter().any(|p| self.boundary.contains(p));
+      }
+
+      let n = ancestors.len();
+      ancestors.extend(self.points.iter().map(|(p, _)| *p));
+      let occupied = self
+         .children
+         .as_ref()
+         .unwrap()
+         .iter()
+         .all(|c| c.occupied_in(r, ancestors));
+      ancestors.truncate(n);
+      occupied
+   }
+
+   /// Every leaf cell with the number of points inside it, counted as for
+   /// `to_density_image`, busiest first. Equal counts are ordered top to
+   /// bottom, then left to right.
+   pub fn leaves_by_population(&self) -> Vec<(Rect<F>, usize)> {
+      let mut leaves = vec![];
+      self.leaf_densities(&mut vec![], &mut leaves);
+      leaves.sort_by(|(a, n), (b, m)| {
+         m.cmp(n)
+            .then(a.y.partial_cmp(&b.y).unwrap_or(Ordering::Equal))
+            .then(a.x.partial_cmp(&b.x).unwrap_or(Ordering::Equal))
+      });
+      leaves
+   }
+
+   fn leaf_densities(&self, ancestors: &mut Vec<Point<F>>, out: &mut Vec<(Rect<F>, usize)>) {
+      if self.divided() {
+         let n = ancestors.len();
+         ancestors.extend(self.points.iter().map(|(p, _)| *p));
+         for c in self.children.as_ref().unwrap().iter() {
+            c.leaf_densities(ancestors, out);
+         }
+         ancestors.truncate(n);
+      } else {
+         let inherited = ancestors
+            .iter()
+            .filter(|p| self.boundary.contains(p))
+            .count();
+         out.push((self.boundary, inherited + self.points.len()));
+      }
+   }
+}
+
+impl<F: Float> QTree<f64, F> {
+   /// For trees whose values are timestamps: appends every point inside `r`
+   /// stamped at or after `since` to `found`.
+   pub fn query_recent<'a>(
+      &'a self,
+      r: &Rect<F>,
+      since: f64,
+      found: &mut Vec<(&'a Point<F>, &'a f64)>,
+   ) {
+      self.query_filter(r, |_, &t| t >= since, found);
+   }
+}
+
+// Subtrees with fewer points than this are searched on the current thread.
+#[cfg(feature = "rayon")]
+const PAR_MIN_POINTS: usize = 4096;
+
+#[cfg(feature = "rayon")]
+impl<T: Sync, F: Float + Sync> QTree<T, F> {
+   /// Returns the same points as `query`, in the same order, searching the
+   /// children of large enough nodes in parallel.
+   pub fn par_query<R>(&self, r: &R) -> Vec<(&Point<F>, &T)>
+   where
+      R: Region<F> + Sync + ?Sized,
+   {
+      let mut found = vec![];
+      if !self.divided() || self.count < PAR_MIN_POINTS {
+         self.query(r, &mut found);
+         return found;
+      }
+      if !r.intersects_rect(&self.boundary) {
+         return found;
+      }
+
+      for (p, v) in &self.points {
+         if r.contains_point(p) {
+            found.push((p, v));
+         }
+      }
+      let [nw, ne, sw, se] = &**self.children.as_ref().unwrap();
+      let ((a, b), (c, d)) = rayon::join(
+         || rayon::join(|| nw.par_query(r), || ne.par_query(r)),
+         || rayon::join(|| sw.par_query(r), || se.par_query(r)),
+      );
+      for part in [a, b, c, d] {
+         found.extend(part);
+      }
+      found
+   }
+}
+
+impl<T, F: Float> Extend<(Point<F>, T)> for QTree<T, F> {
+   fn extend<I: IntoIterator<Item = (Point<F>, T)>>(&mut self, points: I) {
+      for (p, v) in points {
+         self.insert(p, v);
+      }
+   }
+}
+
+// What a `QTree` deserializes from. Subtree counts are recomputed rather
+// than trusted.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct QTreeData<T, F> {
+   boundary: Rect<F>,
+   cap: usize,
+   // these two are missing in data written before they existed
+   #[serde(default)]
+   base_cap: Option<usize>,
+   max_depth: usize,
+   #[serde(default)]
+   wrap: bool,
+   points: Vec<(Point<F>, T)>,
+   children: Option<Box<[QTree<T, F>; 4]>>,
+   overrides: Vec<(Rect<F>, usize)>,
+}
+
+#[cfg(feature = "serde")]
+impl<T, F> From<QTreeData<T, F>> for QTree<T, F> {
+   fn from(d: QTreeData<T, F>) -> Self {
+      let below = d.children.iter().flat_map(|c| c.iter()).map(|c| c.count);
+      Self {
+         count: d.points.len() + below.sum::<usize>(),
+         boundary: d.boundary,
+         cap: d.cap,
+         base_cap: d.base_cap.unwrap_or(d.cap),
+         max_depth: d.max_depth,
+         wrap: d.wrap,
+         points: d.points,
+         children: d.children,
+         overrides: d.overrides,
+      }
+   }
+}
+
+/// Iterator over the points of a [`QTree`], created by [`QTree::iter`].
+pub struct Iter<'a, T, F = f32> {
+   stack: Vec<&'a QTree<T, F>>,
+   points: std::slice::Iter<'a, (Point<F>, T)>,
+}
+
+impl<'a, T, F: Float> Iterator for Iter<'a, T, F> {
+   type Item = (&'a Point<F>, &'a T);
+
+   fn next(&mut self) -> Option<Self::Item> {
+      loop {
+         if let Some((p, v)) = self.points.next() {
+            return Some((p, v));
+         }
+         let node = self.stack.pop()?;
+         if let Some(children) = node.children() {
+            self.stack.extend(children.iter().rev());
+         }
+         self.points = node.points.iter();
+      }
+   }
+}
+
+/// What a [`QTree::query_circle_with_stats`] call did.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CircleQueryStats {
+   /// Nodes skipped because the circle missed their boundary.
+   pub culled: usize,
+   /// Nodes whose points were tested.
+   pub visited: usize,
+   /// Points tested against the circle.
+   pub points_tested: usize,
+}
+
+/// A [`QTree`] with a bounded history of committed states to rewind to.
+///
+/// Each commit stores the tree's boundary and a copy of its points, not its
+/// nodes; rewinding rebuilds the tree from them with the current capacity,
+/// depth limit and capacity overrides.
+#[derive(Debug)]
+pub struct QTreeHistory<T = (), F = f32> {
+   tree: QTree<T, F>,
+   // oldest first, at most `limit` of them
+   snapshots: VecDeque<Snapshot<T, F>>,
+   limit: usize,
+   version: u64,
+}
+
+#[derive(Debug)]
+struct Snapshot<T, F> {
+   version: u64,
+   boundary: Rect<F>,
+   points: Vec<(Point<F>, T)>,
+}
+
+impl<T: Clone, F: Float> QTreeHistory<T, F> {
+   /// Tracks `tree`, keeping the last `limit` commits.
+   pub fn new(tree: QTree<T, F>, limit: usize) -> Self {
+      Self {
+         tree,
+         snapshots: VecDeque::with_capacity(limit),
+         limit,
+         version: 0,
+      }
+   }
+
+   pub fn tree(&self) -> &QTree<T, F> {
+      &self.tree
+   }
+
+   pub fn tree_mut(&mut self) -> &mut QTree<T, F> {
+      &mut self.tree
+   }
+
+   /// Number of commits that can be rewound to.
+   pub fn len(&self) -> usize {
+      self.snapshots.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.snapshots.is_empty()
+   }
+
+   /// Version of the most recent commit still kept.
+   pub fn version(&self) -> Option<u64> {
+      self.snapshots.back().map(|s| s.version)
+   }
+
+   /// Records the current state, dropping the oldest commit once `limit`
+   /// are kept, and returns its version. Versions count up from 1.
+   pub fn commit(&mut self) -> u64 {
+      self.version += 1;
+      if self.limit == 0 {
+         return self.version;
+      }
+      if self.snapshots.len() == self.limit {
+         self.snapshots.pop_front();
+      }
+      self.snapshots.push_back(Snapshot {
+         version: self.version,
+         boundary: self.tree.boundary,
+         points: self.tree.iter().map(|(p, v)| (*p, v.clone())).collect(),
+      });
+      self.version
+   }
+
+   /// Restores the state of `k` commits ago, 0 being the latest, and forgets
+   /// the commits after it. Returns `false`, leaving the tree alone, if fewer
+   /// than `k + 1` commits are kept.
+   pub fn rewind(&mut self, k: usize) -> bool {
+      if k >= self.snapshots.len() {
+         return false;
+      }
+      self.snapshots.truncate(self.snapshots.len() - k);
+      let s = self.snapshots.back().unwrap();
+      let t = &mut self.tree;
+      let mut qt = t.empty_root(s.boundary, t.base_cap, t.max_depth);
+      qt.extend(s.points.iter().cloned());
+      *t = qt;
+      true
+   }
+}
+
+// How much of the sampled rect a node covers, and its matches there.
+type Stratum<'a, T, F> = (F, Vec<(&'a Point<F>, &'a T)>);
+
+fn two<F: Float>() -> F {
+   F::one() + F::one()
+}
+
+// The boundary twice the size of `b` that `QTree::insert_growing` grows it
+// into towards `p`, and its quadrants, with `b` as one of them. `None` if
+// that boundary is no longer finite.
+fn grown_quadrants<F: Float>(b: &Rect<F>, p: &Point<F>) -> Option<(Rect<F>, [Rect<F>; 4])> {
+   // the old root's edges become the new split lines
+   let (x0, x1, x2) = if p.x < b.x {
+      (b.x - b.w, b.x, b.x + b.w)
+   } else {
+      (b.x, b.x + b.w, b.x + two::<F>() * b.w)
+   };
+   let (y0, y1, y2) = if p.y < b.y {
+      (b.y - b.h, b.y, b.y + b.h)
+   } else {
+      (b.y, b.y + b.h, b.y + two::<F>() * b.h)
+   };
+   let outer = Rect::new(x0, y0, x2 - x0, y2 - y0);
+   if ![x0, x2, y0, y2, outer.w, outer.h]
+      .iter()
+      .all(|v| v.is_finite())
+   {
+      return None;
+   }
+   let quadrants = [
+      Rect::new(x0, y0, x1 - x0, y1 - y0),
+      Rect::new(x1, y0, x2 - x1, y1 - y0),
+      Rect::new(x0, y1, x1 - x0, y2 - y1),
+      Rect::new(x1, y1, x2 - x1, y2 - y1),
+   ];
+   Some((outer, quadrants))
+}
+
+// The length `w`, or a little more, for which `origin + w` lies past `v`.
+// The step is sized to the coordinates rather than to `w`, and doubles in
+// case rounding swallows it.
+fn reach_past<F: Float>(origin: F, mut w: F, v: F) -> F {
+   let scale = v.abs().max((origin + w).abs()).max(F::min_positive_value());
+   let mut step = scale * F::epsilon();
+   while v >= origin + w {
+      w = v + step - origin;
+      step = step * two();
+   }
+   w
+}
+
+// Distance from `v` to the span `0..=len` along an axis that repeats every
+// `period`, whichever way round is shorter.
+fn wrapped_gap<F: Float>(v: F, len: F, period: F) -> F {
+   let mut t = v % period;
+   if t < F::zero() {
+      t = t + period;
+   }
+   if t <= len {
+      F::zero()
+   } else {
+      (t - len).min(period - t)
+   }
+}
+
+// Numeric conversion that cannot fail for the float and integer types used
+// here.
+fn cast<F: NumCast>(n: impl ToPrimitive) -> F {
+   F::from(n).unwrap()
+}
+
+// Random index below `len`, from a uniform `[0, 1)` source.
+fn pick(rng: &mut impl FnMut() -> f32, len: usize) -> usize {
+   ((rng() * len as f32) as usize).min(len - 1)
+}
+
+fn mean(total: usize, n: usize) -> f32 {
+   if n == 0 { 0. } else { total as f32 / n as f32 }
+}
+
+// Keeps the `n` best items pushed so far. Smaller keys are better; on equal
+// keys the item pushed first wins.
+struct Best<F, I> {
+   n: usize,
+   pushed: usize,
+   heap: BinaryHeap<Candidate<F, I>>,
+}
+
+impl<F: Float, I> Best<F, I> {
+   fn new(n: usize) -> Self {
+      Self {
+         n,
+         pushed: 0,
+         heap: BinaryHeap::new(),
+      }
+   }
+
+   fn push(&mut self, key: F, item: I) {
+      let c = Candidate {
+         key,
+         seq: self.pushed,
+         item,
+      };
+      self.pushed += 1;
+      if self.heap.len() < self.n {
+         self.heap.push(c);
+      } else if self.heap.peek().is_some_and(|worst| c < *worst) {
+         self.heap.pop();
+         self.heap.push(c);
+      }
+   }
+
+   // Key an item must beat to be kept: the worst kept key once full.
+   fn bound(&self) -> F {
+      if self.heap.len() < self.n {
+         F::infinity()
+      } else {
+         self
+            .heap
+            .peek()
+            .map_or(F::neg_infinity(), |worst| worst.key)
+      }
+   }
+
+   // Items from best to worst.
+   fn into_sorted(self) -> Vec<I> {
+      self
+         .heap
+         .into_sorted_vec()
+         .into_iter()
+         .map(|c| c.item)
+         .collect()
+   }
+}
+
+// Heap entry ordered by key, then by `seq`. A plain `BinaryHeap` keeps the
+// largest on top; wrap in `Reverse` to pop the smallest first. Keys are never
+// NaN.
+struct Candidate<F, I> {
+   key: F,
+   seq: usize,
+   item: I,
+}
+
+impl<F: Float, I> PartialEq for Candidate<F, I> {
+   fn eq(&self, other: &Self) -> bool {
+      self.cmp(other) == Ordering::Equal
+   }
+}
+
+impl<F: Float, I> Eq for Candidate<F, I> {}
+
+impl<F: Float, I> PartialOrd for Candidate<F, I> {
+   fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+      Some(self.cmp(other))
+   }
+}
+
+impl<F: Float, I> Ord for Candidate<F, I> {
+   fn cmp(&self, other: &Self) -> Ordering {
+      let key = self.key.partial_cmp(&other.key).unwrap_or(Ordering::Equal);
+      key.then(self.seq.cmp(&other.seq))
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   // Deterministic xorshift source of floats in [0, 1).
+   fn rng(mut seed: u64) -> impl FnMut() -> f32 {
+      move || {
+         seed ^= seed << 13;
+         seed ^= seed >> 7;
+         seed ^= seed << 17;
+         (seed >> 40) as f32 / (1u64 << 24) as f32
+      }
+   }
+
+   // `n` random points in a 100 x 100 square, each valued by its index.
+   fn random_tree(n: usize, cap: usize, seed: u64) -> (QTree<usize>, Vec<Point>) {
+      let mut r = rng(seed);
+      let points: Vec<_> = (0..n).map(|_| Point::new(r() * 100., r() * 100.)).collect();
+      let qt =
+         QTree::from_points(Rect::new(0., 0., 100., 100.), cap, points.iter().copied().zip(0..));
+      (qt, points)
+   }
+
+   fn everything() -> Rect {
+      Rect::new(0., 0., 100., 100.)
+   }
+
+   // Values found by a query, sorted.
+   fn values(found: &[(&Point, &usize)]) -> Vec<usize> {
+      let mut v: Vec<_> = found.iter().map(|&(_, &i)| i).collect();
+      v.sort();
+      v
+   }
+
+   #[test]
+   fn grid_points_land_in_exactly_one_leaf() {
+      let boundaries = [
+         Rect::new(0., 0., 100., 100.),
+         Rect::new(-7.3, -1.1, 3.7, 9.9),
+         Rect::new(-1000.1, -333.3, 777.7, 0.3),
+      ];
+      for b in boundaries {
+         let mut qt = QTree::new(b, 1);
+         let n = 40;
+         let mut grid = vec![];
+         for i in 0..n {
+            for j in 0..n {
+               let (fx, fy) = (i as f32 / n as f32, j as f32 / n as f32);
+               grid.push(Point::new(b.x + fx * b.w, b.y + fy * b.h));
+            }
+         }
+         // the last representable points before the far edges
+         let far = Point::new(b.x + b.w, b.y + b.h);
+         grid.extend([
+            Point::new(far.x.next_down(), far.y.next_down()),
+            Point::new(b.x, far.y.next_down()),
+            Point::new(far.x.next_down(), b.y),
+         ]);
+         grid.retain(|p| b.contains(p));
+         for p in &grid {
+            assert!(qt.insert(*p, ()), "{p:?} rejected by {b:?}");
+         }
+         assert_eq!(qt.len(), grid.len());
+         assert_eq!(qt.iter().count(), grid.len());
+
+         qt.visit_nodes(&mut |r, points| {
+            for (p, _) in points {
+               assert!(r.contains(p), "{p:?} stored outside {r:?}");
+            }
+         });
+         let mut leaf_cells = vec![];
+         collect_leaves(&qt, &mut leaf_cells);
+         for p in &grid {
+            let holding = leaf_cells.iter().filter(|r| r.contains(p)).count();
+            assert_eq!(holding, 1, "{p:?} in {holding} leaves of {b:?}");
+         }
+      }
+   }
+
+   fn collect_leaves<T>(qt: &QTree<T>, out: &mut Vec<Rect>) {
+      match qt.children() {
+         Some(children) => children.iter().for_each(|c| collect_leaves(c, out)),
+         None => out.push(*qt.boundary()),
+      }
+   }
+
+   #[test]
+   fn duplicate_points_stop_at_max_depth() {
+      let mut qt = QTree::new(Rect::new(0., 0., 100., 100.), 4);
+      for i in 0..100 {
+         assert!(qt.insert(Point::new(50., 50.), i));
+      }
+      assert_eq!(qt.len(), 100);
+      assert!(qt.depth() <= DEFAULT_MAX_DEPTH);
+   }
+
+   #[test]
+   fn insert_growing_reaches_far_points() {
+      let mut qt = QTree::new(Rect::new(0., 0., 1., 1.), 2);
+      qt.insert(Point::new(0.5, 0.5), 0);
+      let far = Point::new(-1234.5, 987.25);
+      assert!(qt.insert_growing(far, 1));
+      assert!(qt.boundary().contains(&far));
+      let mut found = vec![];
+      qt.query(&Rect::new(-1235., 987., 1., 1.), &mut found);
+      assert_eq!(found, vec![(&far, &1)]);
+      assert_eq!(qt.nearest(&Point::new(0.4, 0.4)), Some((&Point::new(0.5, 0.5), &0)));
+      assert_eq!(qt.len(), 2);
+   }
+
+   #[test]
+   fn insert_growing_stops_before_overflowing() {
+      let mut qt = QTree::new(Rect::new(0., 0., 1., 1.), 2);
+      assert!(!qt.insert_growing(Point::new(-3e38, 0.5), ()));
+      assert!(!qt.insert_growing(Point::new(0.5, f32::INFINITY), ()));
+      assert_eq!(qt.boundary(), &Rect::new(0., 0., 1., 1.));
+      assert!(qt.is_empty());
+   }
+
+   #[test]
+   fn insert_growing_keeps_capacity_overrides() {
+      let mut qt = QTree::new(Rect::new(0., 0., 10., 10.), 1);
+      qt.set_capacity_in(&Rect::new(5., 5., 100., 100.), 100);
+      assert!(qt.insert_growing(Point::new(15., 15.), ()));
+      for i in 0..50 {
+         qt.insert(Point::new(11. + i as f32 * 0.05, 12.), ());
+      }
+      // the new SE quadrant lies inside the override, so it never splits
+      assert_eq!(qt.node_count(), 5);
+      assert_eq!(qt.len(), 51);
+   }
+
+   #[test]
+   fn insert_growing_past_an_enclosing_override_uses_the_base_capacity() {
+      let mut qt = QTree::new(Rect::new(0., 0., 10., 10.), 1);
+      qt.set_capacity_in(&Rect::new(-1., -1., 12., 12.), 100);
+      assert!(qt.insert_growing(Point::new(15., 15.), ()));
+      assert_eq!(qt.cap(), 1);
+      let caps: Vec<usize> = qt.children().unwrap().iter().map(|c| c.cap()).collect();
+      assert_eq!(caps, [100, 1, 1, 1]);
+      // the root holds the first point, so the third one splits the corner
+      assert!(qt.insert(Point::new(16., 16.), ()));
+      assert!(qt.insert(Point::new(17., 17.), ()));
+      assert!(qt.children().unwrap()[3].children().is_some());
+   }
+
+   #[test]
+   fn growing_past_an_enclosing_override_uses_the_base_capacity() {
+      let mut qt = QTree::new(Rect::new(0., 0., 10., 10.), 1);
+      qt.set_capacity_in(&Rect::new(-1., -1., 12., 12.), 100);
+      assert_eq!(qt.cap(), 100);
+      assert!(qt.extend_bounds_to_include(&Point::new(15., 15.)));
+      assert_eq!(qt.cap(), 1);
+      for i in 0..10 {
+         qt.insert(Point::new(1. + i as f32 * 0.5, 2.), ());
+      }
+      // the points sit inside the override, so their quadrant stays a leaf
+      assert_eq!(qt.node_count(), 5);
+      assert_eq!(qt.children().unwrap()[0].cap(), 100);
+      assert!(qt.insert(Point::new(14., 14.), ()));
+      assert!(qt.insert(Point::new(14.5, 14.5), ()));
+      assert_eq!(qt.children().unwrap()[3].cap(), 1);
+      assert!(qt.children().unwrap()[3].children().is_some());
+
+      qt.rebuild_to_max_depth(4);
+      assert_eq!(qt.cap(), 1);
+      assert_eq!(qt.children().unwrap()[0].cap(), 100);
+   }
+
+   #[test]
+   fn child_index_of_matches_subdivide_order() {
+      let mut qt: QTree = QTree::new(Rect::new(0., 0., 100., 100.), 1);
+      assert_eq!(qt.child_index_of(&Point::new(10., 10.)), None);
+      qt.subdivide();
+      let points = [(25., 25.), (75., 25.), (25., 75.), (75., 75.)];
+      let children = qt.children().unwrap();
+      for (i, (x, y)) in points.into_iter().enumerate() {
+         let p = Point::new(x, y);
+         assert_eq!(qt.child_index_of(&p), Some(i));
+         assert!(children[i].boundary().contains(&p));
+      }
+      // split lines belong to the east and south quadrants
+      assert_eq!(qt.child_index_of(&Point::new(50., 50.)), Some(3));
+   }
+
+   #[test]
+   fn child_index_of_follows_grown_splits() {
+      let mut qt: QTree = QTree::new(Rect::new(0.1, 0.3, 0.7, 0.9), 1);
+      qt.insert_growing(Point::new(-3.3, -7.7), ());
+      // points on and next to the root's split lines
+      let split = *qt.children().unwrap()[3].boundary();
+      let b = *qt.boundary();
+      let mut seam = vec![];
+      for i in 0..200 {
+         let t = i as f32 / 200.;
+         for d in [split.x.next_down(), split.x, split.x.next_up()] {
+            seam.push(Point::new(d, b.y + t * b.h));
+         }
+         for d in [split.y.next_down(), split.y, split.y.next_up()] {
+            seam.push(Point::new(b.x + t * b.w, d));
+         }
+      }
+      for p in &seam {
+         qt.insert(*p, ());
+      }
+      for p in &seam {
+         let mut node = &qt;
+         while !node.points().iter().any(|(q, _)| q == p) {
+            node = &node.children().unwrap()[node.child_index_of(p).unwrap()];
+         }
+      }
+   }
+
+   #[test]
+   fn remove_keeps_the_rest_and_collapses() {
+      let (mut qt, points) = random_tree(400, 4, 1);
+      let nodes = qt.node_count();
+      for (i, p) in points.iter().enumerate().filter(|(i, _)| i % 2 == 0) {
+         assert_eq!(qt.remove(p), Some(i));
+      }
+      assert_eq!(qt.remove(&Point::new(-1., -1.)), None);
+      let mut found = vec![];
+      qt.query(&everything(), &mut found);
+      let odd: Vec<_> = (0..400).filter(|i| i % 2 == 1).collect();
+      assert_eq!(values(&found), odd);
+      assert_eq!(qt.len(), 200);
+      assert!(qt.node_count() < nodes);
+
+      for (i, p) in points.iter().enumerate().filter(|(i, _)| i % 2 == 1) {
+         assert_eq!(qt.remove(p), Some(i));
+      }
+      assert!(qt.is_empty());
+      assert_eq!(qt.node_count(), 1);
+   }
+
+   #[test]
+   fn query_circle_matches_brute_force() {
+      let (qt, points) = random_tree(1000, 4, 2);
+      let mut r = rng(3);
+      for _ in 0..50 {
+         let c = Circle::new(r() * 120. - 10., r() * 120. - 10., r() * 30.);
+         let mut found = vec![];
+         qt.query_circle(&c, &mut found);
+         let expected: Vec<_> = (0..points.len())
+            .filter(|&i| c.contains(&points[i]))
+            .collect();
+         assert_eq!(values(&found), expected);
+      }
+   }
+
+   #[test]
+   fn nearest_matches_brute_force() {
+      let (qt, points) = random_tree(1000, 4, 4);
+      let mut r = rng(5);
+      for _ in 0..100 {
+         let q = Point::new(r() * 140. - 20., r() * 140. - 20.);
+         let (p, _) = qt.nearest(&q).unwrap();
+         let best = points
+            .iter()
+            .map(|p| p.distance_squared(&q))
+            .fold(f32::INFINITY, f32::min);
+         assert_eq!(p.distance_squared(&q), best);
+      }
+      let empty: QTree = QTree::new(everything(), 4);
+      assert_eq!(empty.nearest(&Point::new(1., 1.)), None);
+   }
+
+   #[test]
+   fn wrapping_nearest_looks_across_the_opposite_edge() {
+      let points = [(1., 50.), (80., 50.), (50., 2.)];
+      let build = || -> QTree<usize> {
+         let points = points
+            .iter()
+            .enumerate()
+            .map(|(i, &(x, y))| (Point::new(x, y), i));
+         QTree::from_points(everything(), 1, points)
+      };
+      let plain = build();
+      let wrapped = build().with_wrap(true);
+      assert!(wrapped.wraps());
+      let probe = Point::new(98., 50.);
+      assert_eq!(plain.nearest(&probe).unwrap().1, &1);
+      // 3 away going right from x = 98 and back in at x = 1
+      assert_eq!(wrapped.nearest(&probe).unwrap().1, &0);
+      assert_eq!(wrapped.nearest(&Point::new(50., 99.)).unwrap().1, &2);
+      let near: Vec<_> = wrapped
+         .k_nearest(&probe, 2)
+         .into_iter()
+         .map(|(_, &v)| v)
+         .collect();
+      assert_eq!(near, [0, 1]);
+   }
+
+   #[test]
+   fn wrapping_queries_match_brute_force() {
+      let (qt, points) = random_tree(500, 4, 40);
+      let qt = qt.with_wrap(true);
+      let gap = |a: f32, b: f32| {
+         let d = (a - b).abs();
+         d.min(100. - d)
+      };
+      let dist2 = |a: &Point, b: &Point| gap(a.x, b.x).powi(2) + gap(a.y, b.y).powi(2);
+      let index = |p: &Point| points.iter().position(|q| q == p).unwrap();
+      let mut r = rng(41);
+      for _ in 0..50 {
+         let q = Point::new(r() * 100., r() * 100.);
+         let best = points
+            .iter()
+            .map(|p| dist2(p, &q))
+            .fold(f32::INFINITY, f32::min);
+         assert_eq!(dist2(qt.nearest(&q).unwrap().0, &q), best);
+
+         let c = Circle::new(q.x, q.y, 12.);
+         let mut found = vec![];
+         qt.query_circle(&c, &mut found);
+         let mut expected: Vec<_> = (0..points.len())
+            .filter(|&i| dist2(&points[i], &q) <= 144.)
+            .collect();
+         expected.sort();
+         assert_eq!(values(&found), expected);
+      }
+
+      let d = 4.;
+      let mut got: Vec<_> = qt
+         .pairs_within(d)
+         .into_iter()
+         .map(|(a, b)| {
+            let (i, j) = (index(a), index(b));
+            (i.min(j), i.max(j))
+         })
+         .collect();
+      got.sort();
+      let mut expected = vec![];
+      for i in 0..points.len() {
+         for j in i + 1..points.len() {
+            if dist2(&points[i], &points[j]) <= d * d {
+               expected.push((i, j));
+            }
+         }
+      }
+      assert_eq!(got, expected);
+      assert!(expected.len() > qt.with_wrap(false).pairs_within(d).len());
+   }
+
+   #[test]
+   fn insert_growing_keeps_wrap_on_the_root_only() {
+      let mut qt: QTree = QTree::new(Rect::new(0., 0., 10., 10.), 1).with_wrap(true);
+      assert!(qt.insert_growing(Point::new(15., 15.), ()));
+      assert!(qt.wraps());
+      assert!(qt.children().unwrap().iter().all(|c| !c.wraps()));
+   }
+
+   #[test]
+   fn nearest_on_a_degenerate_boundary_does_not_panic() {
+      let inverted: QTree = QTree::new(Rect::new(0., 0., -1., -1.), 4);
+      assert_eq!(inverted.nearest(&Point::new(1., 1.)), None);
+      let nan = Rect::new(f32::NAN, 0., 10., 10.);
+      assert!(nan.distance_squared(&Point::new(1., 1.)).is_finite());
+   }
+
+   #[test]
+   fn k_nearest_matches_brute_force() {
+      let (qt, points) = random_tree(1000, 4, 6);
+      let mut r = rng(7);
+      for k in [0, 1, 5, 37, 1000, 1500] {
+         let q = Point::new(r() * 100., r() * 100.);
+         let found = qt.k_nearest(&q, k);
+         let mut dists: Vec<_> = points.iter().map(|p| p.distance_squared(&q)).collect();
+         dists.sort_by(f32::total_cmp);
+         dists.truncate(k);
+         let got: Vec<_> = found.iter().map(|(p, _)| p.distance_squared(&q)).collect();
+         assert_eq!(got, dists, "k = {k}");
+      }
+   }
+
+   #[cfg(feature = "rayon")]
+   #[test]
+   fn par_query_matches_query() {
+      let (qt, _) = random_tree(20_000, 4, 8);
+      let mut r = rng(9);
+      for _ in 0..20 {
+         let rect = Rect::new(r() * 80., r() * 80., r() * 60., r() * 60.);
+         let mut found = vec![];
+         qt.query(&rect, &mut found);
+         assert_eq!(values(&qt.par_query(&rect)), values(&found));
+      }
+      let c = Circle::new(50., 50., 40.);
+      let mut found = vec![];
+      qt.query(&c, &mut found);
+      assert_eq!(values(&qt.par_query(&c)), values(&found));
+   }
+
+   #[cfg(feature = "serde")]
+   #[test]
+   fn json_round_trip_keeps_queries() {
+      let (mut qt, _) = random_tree(500, 4, 10);
+      qt.set_capacity_in(&Rect::new(10., 10., 30., 30.), 9);
+      let json = serde_json::to_string(&qt).unwrap();
+      let back: QTree<usize> = serde_json::from_str(&json).unwrap();
+      assert_eq!(back.len(), qt.len());
+      assert_eq!(back.node_count(), qt.node_count());
+      let r = Rect::new(20., 30., 45., 25.);
+      let (mut a, mut b) = (vec![], vec![]);
+      qt.query(&r, &mut a);
+      back.query(&r, &mut b);
+      assert_eq!(a, b);
+   }
+
+   #[cfg(feature = "serde")]
+   #[test]
+   fn deserialize_recounts_instead_of_trusting() {
+      let json = r#"{"boundary":{"x":0,"y":0,"w":10,"h":10},"cap":4,"max_depth":16,
+         "points":[[{"x":1,"y":1},7]],"count":99,"divided":true,"children":null,
+         "overrides":[]}"#;
+      let mut qt: QTree<usize> = serde_json::from_str(json).unwrap();
+      assert_eq!(qt.len(), 1);
+      assert!(qt.children().is_none());
+      assert!(qt.insert(Point::new(2., 2.), 8));
+      assert_eq!(qt.remove(&Point::new(1., 1.)), Some(7));
+      assert_eq!(qt.len(), 1);
+   }
+
+   #[test]
+   fn query_shapes_returns_the_union_once() {
+      let (qt, points) = random_tree(1000, 4, 11);
+      let rect = Rect::new(20., 20., 30., 30.);
+      let circle = Circle::new(50., 50., 15.);
+      let found = qt.query_shapes(&[&rect, &circle]);
+      let expected: Vec<_> = (0..points.len())
+         .filter(|&i| rect.contains(&points[i]) || circle.contains(&points[i]))
+         .collect();
+      assert!(
+         expected
+            .iter()
+            .any(|&i| rect.contains(&points[i]) && circle.contains(&points[i]))
+      );
+      assert_eq!(values(&found), expected);
+      assert!(qt.query_shapes(&[]).is_empty());
+   }
+
+   #[test]
+   fn query_top_blends_distance_and_priority() {
+      let mut qt = QTree::new(everything(), 2);
+      // (x, priority)
+      for (x, prio) in [
+         (51., 1.),
+         (55., 10.),
+         (60., 3.),
+         (90., 50.),
+         (52., f32::NAN),
+      ] {
+         qt.insert(Point::new(x, 50.), prio);
+      }
+      let center = Point::new(50., 50.);
+      let top = qt.query_top(&everything(), &center, 3, |_, &prio, d| prio - d);
+      let prios: Vec<_> = top.iter().map(|&(_, &v)| v).collect();
+      // 90: 50 - 40, 55: 10 - 5, 51: 1 - 1, 60: 3 - 10
+      assert_eq!(prios, vec![50., 10., 1.]);
+      assert_eq!(
+         qt.query_top(&everything(), &center, 10, |_, &p, d| p - d)
+            .len(),
+         4
+      );
+   }
+
+   #[test]
+   fn rasterize_query_counts_known_layout() {
+      let mut qt = QTree::new(everything(), 2);
+      let layout = [
+         (5., 5.),
+         (6., 7.),
+         (15., 5.),
+         (35., 35.),
+         (39., 39.),
+         (39., 1.),
+         (80., 80.),
+      ];
+      for (x, y) in layout {
+         qt.insert(Point::new(x, y), ());
+      }
+      // 4 x 4 grid of 10 x 10 cells over 0..40; (80, 80) lies outside
+      let grid = qt.rasterize_query(&Rect::new(0., 0., 40., 40.), 4, 4);
+      #[rustfmt::skip]
+      let expected = [
+         2, 1, 0, 1,
+         0, 0, 0, 0,
+         0, 0, 0, 0,
+         0, 0, 0, 2,
+      ];
+      assert_eq!(grid, expected);
+      assert!(qt.rasterize_query(&everything(), 0, 3).is_empty());
+   }
+
+   #[test]
+   fn query_returns_payloads_of_duplicate_points() {
+      let mut qt = QTree::new(everything(), 2);
+      let p = Point::new(42., 17.);
+      for name in ["a", "b", "c", "d", "e"] {
+         qt.insert(p, name);
+      }
+      qt.insert(Point::new(1., 1.), "far");
+      let mut found = vec![];
+      qt.query(&Rect::new(40., 15., 5., 5.), &mut found);
+      let mut names: Vec<_> = found.iter().map(|&(q, &v)| (*q, v)).collect();
+      names.sort_by_key(|&(_, v)| v);
+      assert_eq!(names, ["a", "b", "c", "d", "e"].map(|v| (p, v)));
+   }
+
+   #[test]
+   fn density_image_colors_by_density() {
+      let mut qt = QTree::new(everything(), 1);
+      for (x, y) in [(10., 10.), (60., 10.), (61., 11.), (62., 12.), (63., 13.)] {
+         qt.insert(Point::new(x, y), ());
+      }
+      let (w, h, pixels) = qt.to_density_image(100, 50);
+      assert_eq!((w, h), (100, 50));
+      assert_eq!(pixels.len(), 100 * 50 * 4);
+      let color = |x: f32, y: f32| {
+         let (col, row) = (x as usize, (y / 2.) as usize);
+         let i = (row * 100 + col) * 4;
+         pixels[i..i + 4].to_vec()
+      };
+      let leaves = qt.leaves_by_population();
+      let busiest = leaves[0].0;
+      let single = leaves.iter().find(|(_, n)| *n == 1).unwrap().0;
+      let empty = leaves.iter().find(|(_, n)| *n == 0).unwrap().0;
+      let at = |r: Rect| color(r.x + r.w / 2., r.y + r.h / 2.);
+      assert_ne!(at(busiest), at(single));
+      assert_ne!(at(single), at(empty));
+      assert_ne!(at(busiest), at(empty));
+   }
+
+   #[test]
+   fn defragment_shrinks_without_changing_queries() {
+      let (mut qt, points) = random_tree(2000, 4, 12);
+      for p in &points[..1900] {
+         qt.remove(p);
+      }
+      let mut r = rng(13);
+      let rects: Vec<_> = (0..20)
+         .map(|_| Rect::new(r() * 80., r() * 80., r() * 40., r() * 40.))
+         .collect();
+      let before: Vec<_> = rects
+         .iter()
+         .map(|rect| {
+            let mut found = vec![];
+            qt.query(rect, &mut found);
+            values(&found)
+         })
+         .collect();
+      let nodes = qt.node_count();
+      qt.defragment();
+      assert!(qt.node_count() < nodes, "{} >= {nodes}", qt.node_count());
+      assert_eq!(qt.len(), 100);
+      for (rect, expected) in rects.iter().zip(before) {
+         let mut found = vec![];
+         qt.query(rect, &mut found);
+         assert_eq!(values(&found), expected);
+      }
+   }
+
+   #[test]
+   fn query_border_keeps_only_the_rim() {
+      let (qt, points) = random_tree(1000, 4, 14);
+      let outer = Rect::new(20., 30., 50., 40.);
+      let inner = Rect::new(25., 35., 40., 30.);
+      let mut found = vec![];
+      qt.query_border(&outer, 5., &mut found);
+      let expected: Vec<_> = (0..points.len())
+         .filter(|&i| outer.contains(&points[i]) && !inner.contains(&points[i]))
+         .collect();
+      assert!(!expected.is_empty());
+      assert_eq!(values(&found), expected);
+
+      let mut all = vec![];
+      qt.query(&outer, &mut all);
+      for thickness in [20., 25.] {
+         let mut thick = vec![];
+         qt.query_border(&outer, thickness, &mut thick);
+         assert_eq!(values(&thick), values(&all));
+      }
+   }
+
+   #[test]
+   fn query_means_on_a_uniform_tree() {
+      let mut qt = QTree::new(everything(), 1);
+      for i in 0..8 {
+         for j in 0..8 {
+            qt.insert(Point::new(6.25 + 12.5 * i as f32, 6.25 + 12.5 * j as f32), ());
+         }
+      }
+      let halves = [Rect::new(0., 0., 50., 100.), Rect::new(50., 0., 50., 100.)];
+      assert_eq!(qt.mean_points_per_query(&halves), 32.);
+      let quarter = [Rect::new(0., 0., 50., 50.)];
+      assert_eq!(qt.mean_points_per_query(&quarter), 16.);
+      assert_eq!(qt.mean_points_per_query(&[]), 0.);
+
+      assert_eq!(qt.mean_nodes_per_query(&[everything()]), qt.node_count() as f32);
+      // a rect missing the tree is rejected at the root
+      assert_eq!(qt.nodes_visited(&Rect::new(200., 200., 1., 1.)), 1);
+      let q = qt.nodes_visited(&quarter[0]);
+      assert!(1 < q && q < qt.node_count());
+      assert_eq!(
+         qt.mean_nodes_per_query(&[quarter[0], everything()]),
+         (q + qt.node_count()) as f32 / 2.
+      );
+   }
+
+   #[test]
+   fn query_visit_sees_what_query_returns() {
+      let (qt, _) = random_tree(1000, 4, 15);
+      let mut r = rng(16);
+      for _ in 0..30 {
+         let rect = Rect::new(r() * 100., r() * 100., r() * 50., r() * 50.);
+         let mut found = vec![];
+         qt.query(&rect, &mut found);
+         let mut n = 0;
+         qt.query_visit(&rect, &mut |p, _| {
+            assert!(rect.contains(p));
+            n += 1;
+         });
+         assert_eq!(n, found.len());
+      }
+   }
+
+   #[test]
+   fn xy_arrays_give_back_the_points() {
+      let (qt, points) = random_tree(500, 4, 17);
+      let order: Vec<_> = qt.iter().map(|(_, &i)| i).collect();
+      let len = qt.len();
+      let (xs, ys) = qt.into_xy_arrays();
+      assert_eq!(xs.len(), len);
+      assert_eq!(ys.len(), len);
+      for ((x, y), i) in xs.into_iter().zip(ys).zip(order) {
+         assert_eq!(Point::new(x, y), points[i]);
+      }
+   }
+
+   #[test]
+   fn iter_yields_exactly_the_inserted_points() {
+      let (qt, points) = random_tree(700, 3, 18);
+      let mut seen: Vec<_> = qt.iter().map(|(p, &i)| (i, *p)).collect();
+      seen.sort_by_key(|&(i, _)| i);
+      let expected: Vec<_> = points.into_iter().enumerate().collect();
+      assert_eq!(seen, expected);
+      let empty: QTree = QTree::new(everything(), 3);
+      assert_eq!(empty.iter().count(), 0);
+   }
+
+   #[test]
+   fn capacity_override_splits_less() {
+      let cluster = |cx: f32, cy: f32| {
+         let mut r = rng(19);
+         (0..60)
+            .map(move |_| Point::new(cx + r() * 10., cy + r() * 10.))
+            .collect::<Vec<_>>()
+      };
+      let build = |cx, cy| {
+         let mut qt = QTree::new(everything(), 2);
+         qt.set_capacity_in(&Rect::new(0., 0., 50., 50.), 32);
+         for p in cluster(cx, cy) {
+            qt.insert(p, ());
+         }
+         qt
+      };
+      let inside = build(20., 20.);
+      let outside = build(70., 70.);
+      assert_eq!(inside.len(), outside.len());
+      assert!(
+         inside.node_count() < outside.node_count(),
+         "{} vs {}",
+         inside.node_count(),
+         outside.node_count()
+      );
+      // nodes inside the override carry its capacity
+      let children = inside.children().unwrap();
+      assert_eq!(children[0].cap(), 32);
+      assert_eq!(children[3].cap(), 2);
+   }
+
+   #[test]
+   fn len_counts_only_accepted_inserts() {
+      let mut qt = QTree::new(everything(), 2);
+      let mut accepted = 0;
+      for i in 0..300 {
+         // every third point lands outside, including on the far edges
+         let v = if i % 3 == 0 {
+            100. + (i % 2) as f32
+         } else {
+            (i % 97) as f32
+         };
+         if qt.insert(Point::new(v, (i % 89) as f32), ()) {
+            accepted += 1;
+         }
+      }
+      assert_eq!(accepted, 200);
+      assert_eq!(qt.len(), 200);
+      assert_eq!(qt.iter().count(), 200);
+      qt.remove(&Point::new(1., 1.));
+      assert_eq!(qt.len(), 199);
+   }
+
+   #[test]
+   fn spread_sample_draws_distinct_matches_across_cells() {
+      let (qt, _) = random_tree(2000, 4, 20);
+      let r = Rect::new(10., 10., 60., 60.);
+      let mut source = rng(21);
+      let sample = qt.query_spread_sample(&r, 40, &mut source);
+      assert_eq!(sample.len(), 40);
+      let mut ids = values(&sample);
+      ids.dedup();
+      assert_eq!(ids.len(), 40);
+      assert!(sample.iter().all(|(p, _)| r.contains(p)));
+      let mut cells: Vec<_> = sample
+         .iter()
+         .map(|(p, _)| ((p.x / 10.) as u32, (p.y / 10.) as u32))
+         .collect();
+      cells.sort();
+      cells.dedup();
+      assert!(cells.len() >= 10, "only {} cells", cells.len());
+
+      let mut found = vec![];
+      qt.query(&r, &mut found);
+      let all = qt.query_spread_sample(&r, 100_000, &mut source);
+      assert_eq!(values(&all), values(&found));
+   }
+
+   #[test]
+   fn from_points_matches_insert_loop() {
+      let mut r = rng(22);
+      let points: Vec<_> = (0..800)
+         .map(|i| (Point::new(r() * 120. - 10., r() * 120. - 10.), i))
+         .collect();
+      let built = QTree::from_points(everything(), 4, points.iter().copied());
+      let mut looped = QTree::new(everything(), 4);
+      for &(p, v) in &points {
+         looped.insert(p, v);
+      }
+      let (mut a, mut b) = (vec![], vec![]);
+      built.query(&everything(), &mut a);
+      looped.query(&everything(), &mut b);
+      assert_eq!(a, b);
+      assert_eq!(built.len(), looped.len());
+      assert!(built.len() < points.len());
+   }
+
+   #[test]
+   fn query_recent_filters_by_timestamp() {
+      let mut qt: QTree<f64> = QTree::new(everything(), 2);
+      for i in 0..100 {
+         qt.insert(Point::new((i % 10) as f32 * 10. + 1., (i / 10) as f32 * 10. + 1.), i as f64);
+      }
+      let r = Rect::new(0., 0., 50., 100.);
+      let mut found = vec![];
+      qt.query_recent(&r, 60., &mut found);
+      let mut stamps: Vec<_> = found.iter().map(|&(_, &t)| t).collect();
+      stamps.sort_by(f64::total_cmp);
+      let expected: Vec<_> = (60..100).filter(|i| i % 10 < 5).map(|i| i as f64).collect();
+      assert_eq!(stamps, expected);
+
+      let mut none = vec![];
+      qt.query_recent(&r, 1000., &mut none);
+      assert!(none.is_empty());
+   }
+
+   #[test]
+   fn clear_empties_the_tree() {
+      let (mut qt, _) = random_tree(300, 4, 23);
+      qt.set_capacity_in(&Rect::new(0., 0., 10., 10.), 50);
+      qt.clear();
+      assert_eq!(qt.len(), 0);
+      assert!(qt.is_empty());
+      assert_eq!(qt.node_count(), 1);
+      let mut found = vec![];
+      qt.query(&everything(), &mut found);
+      assert!(found.is_empty());
+      // the tree can be refilled, keeping its settings
+      assert!(qt.insert(Point::new(1., 1.), 0));
+      assert_eq!(qt.len(), 1);
+      assert_eq!(qt.cap(), 4);
+   }
+
+   #[test]
+   fn f64_coordinates_keep_close_points_apart() {
+      let base = 1.0e8;
+      let mut qt: QTree64<usize> = QTree::new(Rect::new(base, base, 1000., 1000.), 2);
+      let points: Vec<_> = (0..50)
+         .map(|i| Point::new(base + 100. + i as f64 * 0.25, base + 100.5))
+         .collect();
+      for (i, p) in points.iter().enumerate() {
+         assert!(qt.insert(*p, i));
+      }
+      // in f32 these would collapse onto a handful of values
+      let mut as_f32: Vec<_> = points.iter().map(|p| p.x as f32).collect();
+      as_f32.dedup();
+      assert!(as_f32.len() < 10);
+
+      assert_eq!(qt.len(), 50);
+      for (i, p) in points.iter().enumerate() {
+         assert_eq!(qt.nearest(p), Some((p, &i)));
+      }
+      let mut found = vec![];
+      qt.query(&Rect::new(base + 100., base, 1., 1000.), &mut found);
+      assert_eq!(found.len(), 4);
+   }
+
+   #[test]
+   fn extend_bounds_moves_an_undivided_boundary() {
+      let mut qt = QTree::new(Rect::new(0., 0., 100., 100.), 4);
+      qt.insert(Point::new(10., 10.), 0);
+      let p = Point::new(150., 100.);
+      assert!(qt.extend_bounds_to_include(&p));
+      assert!(qt.children().is_none());
+      assert!(qt.boundary().contains(&p));
+      assert!(qt.insert(p, 1));
+      assert_eq!(qt.len(), 2);
+      assert!(!qt.extend_bounds_to_include(&Point::new(f32::NAN, 0.)));
+   }
+
+   #[test]
+   fn extend_bounds_rebuilds_a_divided_tree() {
+      let (mut qt, points) = random_tree(200, 2, 24);
+      assert!(qt.children().is_some());
+      let p = Point::new(-30., 250.);
+      assert!(qt.extend_bounds_to_include(&p));
+      assert!(qt.boundary().contains(&p));
+      assert_eq!(qt.len(), 200);
+      assert!(qt.insert(p, 200));
+      let b = *qt.boundary();
+      let mut found = vec![];
+      qt.query(&b, &mut found);
+      assert_eq!(values(&found), (0..=points.len()).collect::<Vec<_>>());
+   }
+
+   #[test]
+   fn count_in_matches_query() {
+      let (qt, _) = random_tree(2000, 3, 25);
+      let mut r = rng(26);
+      for _ in 0..200 {
+         let rect = Rect::new(r() * 120. - 10., r() * 120. - 10., r() * 80., r() * 80.);
+         let mut found = vec![];
+         qt.query(&rect, &mut found);
+         assert_eq!(qt.count_in(&rect), found.len());
+      }
+      assert_eq!(qt.count_in(&Rect::new(-1., -1., 200., 200.)), 2000);
+   }
+
+   #[test]
+   fn query_visit_controlled_can_stop_early() {
+      let (qt, _) = random_tree(500, 2, 27);
+      let mut n = 0;
+      let flow = qt.query_visit_controlled(&everything(), &mut |_, _| {
+         n += 1;
+         if n == 5 {
+            ControlFlow::Break(())
+         } else {
+            ControlFlow::Continue(())
+         }
+      });
+      assert!(flow.is_break());
+      assert_eq!(n, 5);
+
+      let mut n = 0;
+      let flow = qt.query_visit_controlled(&everything(), &mut |_, _| {
+         n += 1;
+         ControlFlow::Continue(())
+      });
+      assert!(flow.is_continue());
+      assert_eq!(n, 500);
+   }
+
+   #[test]
+   fn stats_of_a_small_tree() {
+      let mut qt: QTree = QTree::new(everything(), 1);
+      assert_eq!((qt.depth(), qt.node_count(), qt.leaf_count()), (0, 1, 1));
+      qt.insert(Point::new(10., 10.), ());
+      qt.insert(Point::new(60., 60.), ());
+      assert_eq!((qt.depth(), qt.node_count(), qt.leaf_count()), (1, 5, 4));
+      qt.insert(Point::new(70., 70.), ());
+      assert_eq!((qt.depth(), qt.node_count(), qt.leaf_count()), (2, 9, 7));
+   }
+
+   #[test]
+   fn rebuild_to_max_depth_bounds_depth() {
+      let (mut qt, _) = random_tree(1000, 1, 28);
+      for i in 0..50 {
+         qt.insert(Point::new(5., 5.), 1000 + i);
+      }
+      for d in [0, 1, 2, 3, 5] {
+         qt.rebuild_to_max_depth(d);
+         assert!(qt.depth() <= d, "depth {} > {d}", qt.depth());
+         assert_eq!(qt.max_depth(), d);
+         assert_eq!(qt.len(), 1050);
+      }
+      assert_eq!(qt.cap(), 2);
+      let mut empty: QTree = QTree::new(everything(), 5);
+      empty.rebuild_to_max_depth(3);
+      assert_eq!(empty.cap(), 1);
+   }
+
+   // Points with |x - y| < 5, to check a custom region.
+   struct Diagonal;
+
+   impl Region for Diagonal {
+      fn contains_point(&self, p: &Point) -> bool {
+         (p.x - p.y).abs() < 5.
+      }
+
+      fn intersects_rect(&self, r: &Rect) -> bool {
+         r.x - (r.y + r.h) < 5. && (r.x + r.w) - r.y > -5.
+      }
+   }
+
+   #[test]
+   fn query_accepts_any_region() {
+      let (qt, points) = random_tree(1000, 3, 29);
+      let (mut by_rect, mut by_circle) = (vec![], vec![]);
+      qt.query(&everything(), &mut by_rect);
+      qt.query(&Circle::new(50., 50., 1000.), &mut by_circle);
+      assert_eq!(by_rect.len(), 1000);
+      assert_eq!(values(&by_rect), values(&by_circle));
+
+      let mut diagonal = vec![];
+      let region: &dyn Region = &Diagonal;
+      qt.query(region, &mut diagonal);
+      let expected: Vec<_> = (0..points.len())
+         .filter(|&i| Diagonal.contains_point(&points[i]))
+         .collect();
+      assert_eq!(values(&diagonal), expected);
+   }
+
+   #[test]
+   fn query_and_mark_returns_each_point_once() {
+      let mut r = rng(30);
+      let mut qt = QTree::new(everything(), 2);
+      for _ in 0..500 {
+         qt.insert(Point::new(r() * 100., r() * 100.), false);
+      }
+      let first = Rect::new(0., 0., 60., 60.);
+      let second = Rect::new(40., 40., 60., 60.);
+      let visit = |seen: &mut bool| !std::mem::replace(seen, true);
+      let a = qt.query_and_mark(&first, visit);
+      let b = qt.query_and_mark(&second, visit);
+      let overlap = Rect::new(40., 40., 20., 20.);
+      assert!(qt.count_in(&overlap) > 0);
+      assert_eq!(a.len(), qt.count_in(&first));
+      assert_eq!(
+         a.len() + b.len(),
+         qt.count_in(&first) + qt.count_in(&second) - qt.count_in(&overlap)
+      );
+      assert!(b.iter().all(|p| !first.contains(p)));
+   }
+
+   #[test]
+   fn leaves_by_population_puts_the_heavy_cell_first() {
+      let mut qt = QTree::new(everything(), 2);
+      for (x, y) in [(10., 10.), (60., 10.), (10., 60.)] {
+         qt.insert(Point::new(x, y), ());
+      }
+      for i in 0..6 {
+         qt.insert(Point::new(80. + i as f32, 80.), ());
+      }
+      let leaves = qt.leaves_by_population();
+      assert_eq!(leaves[0], (Rect::new(75., 75., 12.5, 12.5), 6));
+      assert_eq!(leaves.len(), qt.leaf_count());
+      assert_eq!(leaves.iter().map(|&(_, n)| n).sum::<usize>(), 9);
+      for pair in leaves.windows(2) {
+         let ((a, n), (b, m)) = (pair[0], pair[1]);
+         assert!(n > m || (n == m && (a.y, a.x) <= (b.y, b.x)));
+      }
+   }
+
+   #[test]
+   fn prune_collapses_empty_quadrants() {
+      let mut qt = QTree::new(Rect::new(0., 0., 10., 10.), 1);
+      qt.insert_growing(Point::new(1000., 1000.), ());
+      qt.subdivide();
+      for i in 0..20 {
+         qt.insert(Point::new(5. + i as f32 * 0.1, 5.), ());
+      }
+      assert!(qt.node_count() > 20);
+      for i in 0..20 {
+         qt.remove(&Point::new(5. + i as f32 * 0.1, 5.));
+      }
+      assert_eq!(qt.len(), 1);
+      let nodes = qt.node_count();
+      let freed = qt.prune();
+      assert_eq!(qt.node_count(), nodes - freed);
+      assert!(qt.node_count() <= 5, "{} nodes left", qt.node_count());
+      assert_eq!(qt.prune(), 0);
+
+      let mut fresh: QTree = QTree::new(everything(), 1);
+      fresh.subdivide();
+      assert_eq!(fresh.prune(), 4);
+      assert_eq!(fresh.node_count(), 1);
+   }
+
+   #[test]
+   fn is_fully_occupied_works_per_leaf() {
+      let mut qt: QTree = QTree::new(everything(), 1);
+      // three quadrants hold a point, the SW one stays empty
+      for (x, y) in [(10., 10.), (60., 10.), (60., 60.)] {
+         qt.insert(Point::new(x, y), ());
+      }
+      assert!(qt.is_fully_occupied(&Rect::new(0., 0., 100., 40.)));
+      assert!(qt.is_fully_occupied(&Rect::new(55., 20., 40., 70.)));
+      assert!(!qt.is_fully_occupied(&everything()));
+      assert!(!qt.is_fully_occupied(&Rect::new(10., 60., 5., 5.)));
+      // nothing overlapped, nothing empty
+      assert!(qt.is_fully_occupied(&Rect::new(200., 200., 5., 5.)));
+   }
+
+   #[test]
+   fn pairs_within_matches_brute_force() {
+      let (mut qt, mut points) = random_tree(400, 3, 31);
+      // coincident points still form a pair
+      qt.insert(points[0], 400);
+      points.push(points[0]);
+      let d = 4.;
+      let index = |p: &Point| points.iter().position(|q| q == p).unwrap();
+      let mut got: Vec<_> = qt
+         .pairs_within(d)
+         .into_iter()
+         .map(|(a, b)| {
+            let (i, j) = (index(a), index(b));
+            (i.min(j), i.max(j))
+         })
+         .collect();
+      got.sort();
+      let mut expected = vec![];
+      for i in 0..points.len() {
+         for j in i + 1..points.len() {
+            if points[i].distance_squared(&points[j]) <= d * d {
+               let (a, b) = (index(&points[i]), index(&points[j]));
+               expected.push((a.min(b), a.max(b)));
+            }
+         }
+      }
+      expected.sort();
+      assert!(expected.len() > 10);
+      assert_eq!(got, expected);
+      // a negative distance is not read as its absolute value
+      assert!(qt.pairs_within(-d).is_empty());
+      assert_eq!(qt.pairs_within(0.).len(), 1);
+   }
+
+   #[test]
+   fn pairs_within_finds_pairs_across_a_split_line() {
+      let mut qt = QTree::new(everything(), 1);
+      qt.insert(Point::new(10., 10.), ());
+      qt.insert(Point::new(49.8, 30.), ());
+      qt.insert(Point::new(50.1, 30.), ());
+      let children = qt.children().unwrap();
+      assert_eq!(children[0].len(), 1);
+      assert_eq!(children[1].len(), 1);
+      let pairs = qt.pairs_within(0.5);
+      assert_eq!(pairs.len(), 1);
+      let (a, b) = pairs[0];
+      let mut xs = [a.x, b.x];
+      xs.sort_by(f32::total_cmp);
+      assert_eq!(xs, [49.8, 50.1]);
+   }
+
+   #[test]
+   fn history_rewinds_to_committed_states() {
+      let mut history = QTreeHistory::new(QTree::new(everything(), 2), 3);
+      let probe = Rect::new(0., 0., 30., 100.);
+      let mut states = vec![];
+      for k in 0..5 {
+         history
+            .tree_mut()
+            .insert(Point::new(10. * k as f32 + 1., 5.), k);
+         assert_eq!(history.commit(), k as u64 + 1);
+         let mut found = vec![];
+         history.tree().query(&probe, &mut found);
+         states.push(values(&found));
+      }
+      assert_eq!(history.len(), 3);
+      assert_eq!(history.version(), Some(5));
+
+      history
+         .tree_mut()
+         .insert_growing(Point::new(500., 500.), 99);
+      history.tree_mut().remove(&Point::new(1., 5.));
+      // only the last three commits are kept
+      assert!(!history.rewind(3));
+      assert_eq!(history.tree().len(), 5);
+
+      assert!(history.rewind(0));
+      assert_eq!(history.tree().boundary(), &everything());
+      assert_eq!(history.tree().len(), 5);
+      assert!(history.rewind(2));
+      assert_eq!(history.version(), Some(3));
+      let mut found = vec![];
+      history.tree().query(&probe, &mut found);
+      assert_eq!(values(&found), states[2]);
+      assert_eq!(history.len(), 1);
+      assert_eq!(history.commit(), 6);
+   }
+
+   #[test]
+   fn history_with_no_room_keeps_nothing() {
+      let mut history = QTreeHistory::new(QTree::new(everything(), 2), 0);
+      history.tree_mut().insert(Point::new(1., 1.), ());
+      assert_eq!(history.commit(), 1);
+      assert!(history.is_empty());
+      assert_eq!(history.version(), None);
+      assert!(!history.rewind(0));
+      assert_eq!(history.tree().len(), 1);
+   }
+
+   #[test]
+   fn update_moves_points_near_and_far() {
+      let (mut qt, points) = random_tree(300, 1, 32);
+      let nodes = qt.node_count();
+      let p = points[7];
+      let near = Point::new(p.x + 1e-4, p.y);
+      assert!(qt.update(&p, near));
+      assert_eq!(qt.node_count(), nodes);
+      assert_eq!(qt.nearest(&near), Some((&near, &7)));
+
+      let far = Point::new(100. - p.x, 100. - p.y);
+      assert!(qt.update(&near, far));
+      assert_eq!(qt.nearest(&far), Some((&far, &7)));
+      assert_eq!(qt.len(), 300);
+
+      // out-of-bounds targets and missing points leave the tree alone
+      assert!(!qt.update(&far, Point::new(150., 50.)));
+      assert_eq!(qt.nearest(&far), Some((&far, &7)));
+      assert!(!qt.update(&Point::new(-1., -1.), far));
+      assert_eq!(qt.len(), 300);
+      for p in points
+         .iter()
+         .enumerate()
+         .filter(|&(i, _)| i != 7)
+         .map(|(_, p)| p)
+      {
+         assert!(qt.remove(p).is_some());
+      }
+      assert_eq!(qt.remove(&far), Some(7));
+   }
+
+   #[test]
+   fn circle_stats_show_culling_on_wide_cells() {
+      let mut r = rng(33);
+      let mut qt = QTree::new(Rect::new(0., 0., 1000., 10.), 2);
+      let mut points = vec![];
+      for i in 0..1000 {
+         let p = Point::new(r() * 1000., r() * 10.);
+         points.push(p);
+         qt.insert(p, i);
+      }
+      for _ in 0..50 {
+         let c = Circle::new(r() * 1000., r() * 10., r() * 30.);
+         let (found, stats) = qt.query_circle_with_stats(&c);
+         let expected: Vec<_> = (0..points.len())
+            .filter(|&i| c.contains(&points[i]))
+            .collect();
+         assert_eq!(values(&found), expected);
+         assert!(stats.culled > 0);
+         assert!(stats.visited >= 1);
+         assert!(stats.points_tested >= found.len());
+         assert!(stats.visited + stats.culled <= qt.node_count());
+      }
+   }
+
+   #[test]
+   fn visit_nodes_sees_every_node_once() {
+      let (qt, _) = random_tree(500, 2, 34);
+      let (mut nodes, mut points) = (0, 0);
+      let mut first = None;
+      qt.visit_nodes(&mut |b, p| {
+         first.get_or_insert(*b);
+         nodes += 1;
+         points += p.len();
+      });
+      assert_eq!(nodes, qt.node_count());
+      assert_eq!(points, qt.len());
+      assert_eq!(first.as_ref(), Some(qt.boundary()));
+   }
+
+   #[test]
+   fn extend_bounds_from_a_zero_size_boundary() {
+      let mut qt: QTree = QTree::new(Rect::new(5., 5., 0., 0.), 4);
+      let p = Point::new(5., 7.);
+      assert!(qt.extend_bounds_to_include(&p));
+      assert!(qt.boundary().contains(&p));
+      assert!(qt.insert(p, ()));
+
+      let mut qt: QTree64 = QTree::new(Rect::new(5., 5., 0., 0.), 4);
+      assert!(qt.extend_bounds_to_include(&Point::new(5., 7.)));
+      assert!(qt.insert(Point::new(5., 7.), ()));
+   }
+
+   #[test]
+   fn extend_bounds_to_the_far_edge_at_large_coordinates() {
+      let mut qt: QTree64 = QTree::new(Rect::new(1e12, 0., 1., 1.), 4);
+      let p = Point::new(1e12 + 1., 0.5);
+      assert!(qt.extend_bounds_to_include(&p));
+      assert!(qt.insert(p, ()));
+      // the edge moves by about one step at that magnitude, not by 1
+      assert!(qt.boundary().w < 1.01);
+
+      let mut qt: QTree = QTree::new(Rect::new(1e8, 1e8, 1., 1.), 4);
+      let p = Point::new(1e8 + 64., 1e8 + 64.);
+      assert!(qt.extend_bounds_to_include(&p));
+      assert!(qt.insert(p, ()));
+   }
+}